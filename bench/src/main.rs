@@ -0,0 +1,319 @@
+#![feature(std_misc)]
+
+extern crate md;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+use std::borrow::Cow;
+
+use md::{MarkdownParser, Document, Text, Inline, Block, Chunk, Code, BlockCode};
+use md::visit::{self, Visitor};
+use md::render::xml;
+
+const USAGE: &'static str = "\
+Usage: bench [options] [corpus...]
+
+Measures parsing and rendering throughput on the corpora and compares it with
+baseline.txt. Every subdirectory of corpus/ is a corpus of documents; the
+pathological corpus is generated.
+
+Targets:
+    parse       MarkdownParser::read_all
+    render      xml::to_xml over the parsed documents
+
+Options:
+    --save          write the results to baseline.txt
+    --tolerance P   allowed slowdown against the baseline, in percent
+                    (default: 10)
+
+Exits with 2 on a slowdown and with 3 if some measurement has no baseline.
+";
+
+// each measurement runs at least this long
+const MIN_NANOS: i64 = 500_000_000;
+
+struct Corpus {
+    name: String,
+    docs: Vec<Vec<u8>>
+}
+
+impl Corpus {
+    fn bytes(&self) -> usize {
+        self.docs.iter().map(|d| d.len()).fold(0, |a, b| a + b)
+    }
+}
+
+struct Measurement {
+    corpus: String,
+    target: &'static str,
+    mb_per_s: f64,
+    // average per document
+    buffers: usize
+}
+
+// Counts heap buffers held by the token tree: non-empty vectors and owned
+// strings. This is not the number of allocations made by the parser, which
+// also allocates temporarily and grows vectors.
+struct BufferCounter(usize);
+
+impl<'a> Visitor<'a> for BufferCounter {
+    fn visit_document(&mut self, doc: &Document<'a>) {
+        if !doc.is_empty() { self.0 += 1; }
+        visit::walk_document(self, doc);
+    }
+
+    fn visit_list_item(&mut self, item: &Document<'a>) {
+        self.visit_document(item);
+    }
+
+    fn visit_block(&mut self, block: &Block<'a>) {
+        if let BlockCode { ref content, .. } = *block {
+            self.count_cow(content);
+        }
+        visit::walk_block(self, block);
+    }
+
+    fn visit_text(&mut self, text: &Text<'a>) {
+        if !text.is_empty() { self.0 += 1; }
+        visit::walk_text(self, text);
+    }
+
+    fn visit_inline(&mut self, inline: &Inline<'a>) {
+        match *inline {
+            Chunk(ref s) | Code(ref s) => self.count_cow(s),
+            _ => {}
+        }
+        visit::walk_inline(self, inline);
+    }
+}
+
+impl BufferCounter {
+    fn count_cow(&mut self, s: &Cow<str>) {
+        if let Cow::Owned(_) = *s { self.0 += 1; }
+    }
+}
+
+fn count_buffers(doc: &Document) -> usize {
+    let mut counter = BufferCounter(0);
+    counter.visit_document(doc);
+    counter.0
+}
+
+fn read_corpus(dir: &Path, name: String) -> Corpus {
+    let mut docs = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths.iter() {
+            let mut buf = Vec::new();
+            if File::open(path).and_then(|mut f| f.read_to_end(&mut buf)).is_ok() {
+                docs.push(buf);
+            }
+        }
+    }
+    Corpus { name: name, docs: docs }
+}
+
+// inputs which are slow for naive parsers
+fn pathological() -> Corpus {
+    fn repeat(s: &str, n: usize) -> String {
+        let mut result = String::with_capacity(s.len() * n);
+        for _ in 0..n { result.push_str(s); }
+        result
+    }
+
+    let mut nested_lists = String::new();
+    for i in 0..200 {
+        nested_lists.push_str(repeat("  ", i).as_slice());
+        nested_lists.push_str("- item\n");
+    }
+    let mut emphasis_chain = String::new();
+    for i in 0..5000 {
+        emphasis_chain.push_str(if i % 2 == 0 { "*a " } else { "_b " });
+    }
+    for i in (0..5000).rev() {
+        emphasis_chain.push_str(if i % 2 == 0 { "c*" } else { "d_" });
+    }
+    let mut definitions = String::new();
+    for i in 0..10000 {
+        definitions.push_str(format!("[{}]: /url{}\n[{}]\n", i, i, i).as_slice());
+    }
+
+    let docs = vec![
+        repeat(">", 10000) + " quote\n",
+        nested_lists,
+        repeat("> - ", 5000) + "item\n",
+        emphasis_chain,
+        repeat("*", 10000) + "a" + repeat("*", 10000).as_slice(),
+        repeat("_a ", 10000),
+        repeat("[", 10000) + "a" + repeat("]", 10000).as_slice(),
+        // openers without closers
+        repeat("a * ", 10000),
+        repeat("a ** ", 10000),
+        repeat("[", 10000) + "a",
+        repeat("`", 5000) + "a",
+        definitions,
+        repeat("a", 1 << 20)
+    ];
+    Corpus {
+        name: "pathological".to_string(),
+        docs: docs.into_iter().map(|d| d.into_bytes()).collect()
+    }
+}
+
+// runs f over the corpus until enough time has passed; returns MB/s
+fn throughput<F>(corpus: &Corpus, mut f: F) -> f64 where F: FnMut(&[u8]) {
+    let mut rounds = 0;
+    let mut nanos = 0;
+    while nanos < MIN_NANOS {
+        let d = Duration::span(|| {
+            for doc in corpus.docs.iter() {
+                f(doc.as_slice());
+            }
+        });
+        nanos += d.num_nanoseconds().unwrap_or(0);
+        rounds += 1;
+    }
+    let bytes = (corpus.bytes() * rounds) as f64;
+    bytes / (1 << 20) as f64 / (nanos as f64 / 1e9)
+}
+
+fn measure(corpus: &Corpus) -> Vec<Measurement> {
+    let buffers = corpus.docs.iter()
+        .map(|d| count_buffers(&MarkdownParser::new(d.as_slice()).read_all()))
+        .fold(0, |a, b| a + b) / corpus.docs.len();
+
+    let parse = throughput(corpus, |d| { MarkdownParser::new(d).read_all(); });
+
+    let docs: Vec<_> = corpus.docs.iter().map(|d| MarkdownParser::new(d.as_slice()).read_all()).collect();
+    let mut i = 0;
+    let render = throughput(corpus, |_| {
+        xml::to_xml(&docs[i % docs.len()]);
+        i += 1;
+    });
+
+    vec![
+        Measurement { corpus: corpus.name.clone(), target: "parse", mb_per_s: parse, buffers: buffers },
+        Measurement { corpus: corpus.name.clone(), target: "render", mb_per_s: render, buffers: buffers }
+    ]
+}
+
+// lines of the baseline file: corpus, target, MB/s and tree buffers
+fn read_baseline(path: &Path) -> Vec<(String, String, f64, usize)> {
+    let mut source = String::new();
+    if File::open(path).and_then(|mut f| f.read_to_string(&mut source)).is_err() {
+        return Vec::new();
+    }
+    source.lines()
+        .filter(|l| !l.starts_with("#") && !l.trim().is_empty())
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.split(' ').filter(|f| !f.is_empty()).collect();
+            if fields.len() != 4 { return None }
+            match (fields[2].parse::<f64>(), fields[3].parse::<usize>()) {
+                (Ok(speed), Ok(buffers)) =>
+                    Some((fields[0].to_string(), fields[1].to_string(), speed, buffers)),
+                _ => None
+            }
+        })
+        .collect()
+}
+
+fn save_baseline(path: &Path, results: &[Measurement]) -> io::Result<()> {
+    let mut out = String::new();
+    out.push_str("# Throughput baseline, one line per measurement:\n");
+    out.push_str("#     corpus target MB/s tree-buffers\n");
+    out.push_str("# Rewrite it with `cargo run --release -- --save` on the reference machine\n");
+    out.push_str("# after an intended performance change, and commit the result.\n");
+    for m in results.iter() {
+        out.push_str(format!("{} {} {:.2} {}\n", m.corpus, m.target, m.mb_per_s, m.buffers).as_slice());
+    }
+    File::create(path).and_then(|mut f| f.write_all(out.as_bytes()))
+}
+
+fn usage_error(message: String) -> i32 {
+    let _ = writeln!(&mut io::stderr(), "bench: {}\n\n{}", message, USAGE);
+    1
+}
+
+fn run(args: &[String]) -> i32 {
+    let mut save = false;
+    let mut tolerance = 10.0;
+    let mut only = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_slice() {
+            "--help" | "-h" => { print!("{}", USAGE); return 0 }
+            "--save" => save = true,
+            "--tolerance" => match args.next().and_then(|v| v.parse::<f64>().ok()) {
+                Some(value) => tolerance = value,
+                None => return usage_error("--tolerance needs a number".to_string())
+            },
+            name => only.push(name.to_string())
+        }
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut corpora = Vec::new();
+    if let Ok(entries) = fs::read_dir(&root.join("corpus")) {
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths.iter() {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+            if let Some(name) = name {
+                corpora.push(read_corpus(path, name));
+            }
+        }
+    }
+    corpora.push(pathological());
+    corpora.retain(|c| !c.docs.is_empty() && (only.is_empty() || only.contains(&c.name)));
+
+    let baseline_path = root.join("baseline.txt");
+    let baseline = read_baseline(&baseline_path);
+
+    let mut results = Vec::new();
+    let mut regressions = 0;
+    let mut missing = 0;
+    println!("{:<16} {:<8} {:>10} {:>10} {:>12}", "corpus", "target", "MB/s", "baseline", "buffers");
+    for corpus in corpora.iter() {
+        for m in measure(corpus).into_iter() {
+            let base = baseline.iter().find(|b| b.0 == m.corpus && b.1 == m.target);
+            let mut note = "";
+            match base {
+                Some(&(_, _, speed, buffers)) => {
+                    if m.mb_per_s < speed * (1.0 - tolerance / 100.0) { note = "  slower"; regressions += 1; }
+                    else if m.buffers > buffers { note = "  more buffers"; regressions += 1; }
+                }
+                None => { note = "  no baseline"; missing += 1; }
+            }
+            println!("{:<16} {:<8} {:>10.2} {:>10} {:>12}{}", m.corpus, m.target, m.mb_per_s,
+                     base.map(|b| format!("{:.2}", b.2)).unwrap_or("-".to_string()), m.buffers, note);
+            results.push(m);
+        }
+    }
+
+    if save {
+        if let Err(e) = save_baseline(&baseline_path, results.as_slice()) {
+            let _ = writeln!(&mut io::stderr(), "can't save {}: {}", baseline_path.display(), e);
+            return 1;
+        }
+        println!("saved {}", baseline_path.display());
+        return 0;
+    }
+    if regressions > 0 { return 2 }
+    if missing > 0 {
+        // a missing baseline must not pass for a successful comparison
+        let _ = writeln!(&mut io::stderr(), "{} measurements have no baseline, record it with --save", missing);
+        return 3;
+    }
+    0
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(run(&args));
+}
@@ -0,0 +1,85 @@
+#![feature(test)]
+
+extern crate test;
+extern crate md;
+
+use test::Bencher;
+
+use md::MarkdownParser;
+
+// paragraphs of n lines, each made of the given piece repeated
+fn paragraphs(piece: &str, n: usize) -> String {
+    let mut result = String::new();
+    for i in 0..n {
+        for _ in 0..8 {
+            result.push_str(piece);
+        }
+        result.push_str(if i % 8 == 7 { "\n\n" } else { "\n" });
+    }
+    result
+}
+
+fn bench_parse(b: &mut Bencher, input: &str) {
+    b.bytes = input.len() as u64;
+    b.iter(|| MarkdownParser::new(input.as_bytes()).read_all());
+}
+
+// the same with plain text read byte by byte, as before the fast path; run
+// with `cargo bench --features bench`
+#[cfg(feature = "bench")]
+fn bench_parse_bytewise(b: &mut Bencher, input: &str) {
+    b.bytes = input.len() as u64;
+    b.iter(|| MarkdownParser::new(input.as_bytes()).read_bytewise().read_all());
+}
+
+#[bench]
+fn plain_text(b: &mut Bencher) {
+    let input = paragraphs("Lorem ipsum dolor sit amet, consectetur adipiscing elit. ", 1000);
+    bench_parse(b, input.as_slice());
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn plain_text_bytewise(b: &mut Bencher) {
+    let input = paragraphs("Lorem ipsum dolor sit amet, consectetur adipiscing elit. ", 1000);
+    bench_parse_bytewise(b, input.as_slice());
+}
+
+#[bench]
+fn sparse_markup(b: &mut Bencher) {
+    let input = paragraphs("Lorem ipsum *dolor* sit amet, `consectetur` adipiscing [elit](x). ", 1000);
+    bench_parse(b, input.as_slice());
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn sparse_markup_bytewise(b: &mut Bencher) {
+    let input = paragraphs("Lorem ipsum *dolor* sit amet, `consectetur` adipiscing [elit](x). ", 1000);
+    bench_parse_bytewise(b, input.as_slice());
+}
+
+#[bench]
+fn dense_markup(b: &mut Bencher) {
+    let input = paragraphs("*a* _b_ `c` \\* [d] **e** ", 1000);
+    bench_parse(b, input.as_slice());
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn dense_markup_bytewise(b: &mut Bencher) {
+    let input = paragraphs("*a* _b_ `c` \\* [d] **e** ", 1000);
+    bench_parse_bytewise(b, input.as_slice());
+}
+
+#[bench]
+fn unicode_text(b: &mut Bencher) {
+    let input = paragraphs("Съешь же ещё этих мягких французских булок, да выпей чаю. ", 1000);
+    bench_parse(b, input.as_slice());
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn unicode_text_bytewise(b: &mut Bencher) {
+    let input = paragraphs("Съешь же ещё этих мягких французских булок, да выпей чаю. ", 1000);
+    bench_parse_bytewise(b, input.as_slice());
+}
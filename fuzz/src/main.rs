@@ -0,0 +1,237 @@
+extern crate md;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+
+use md::{MarkdownParser, StreamingParser};
+use md::parser::MarkdownConfig;
+use md::render::{markdown, ansi, latex, plain, roff, xml};
+use md::render::ansi::AnsiStyle;
+use md::render::plain::PlainStyle;
+
+const USAGE: &'static str = "\
+Usage: fuzz [options] [target]
+
+Replays inputs from the regressions directory, then runs the target on random
+inputs derived from them. Inputs which make the target panic are saved to the
+crashes directory; turn them into regressions once they are fixed.
+
+Targets:
+    parse       MarkdownParser::read_all with default and tight limits
+    stream      StreamingParser over the input
+    render      every renderer over the parsed document
+    all         all of the above (default)
+
+Options:
+    -n N        number of random inputs (default: 10000)
+    -s SEED     seed of the random generator (default: 1)
+    --replay    only replay the regressions
+";
+
+static TARGETS: &'static [(&'static str, fn(&[u8]))] = &[
+    ("parse", parse as fn(&[u8])),
+    ("stream", stream as fn(&[u8])),
+    ("render", render as fn(&[u8]))
+];
+
+// bytes which are likely to start or end some Markdown construct
+static SPECIAL: &'static [u8] = b"\\`*_[]()<>!#-+=~.:'\"| \t\n\r0123456789";
+
+fn parse(input: &[u8]) {
+    MarkdownParser::new(input).read_all();
+
+    let config = MarkdownConfig::default()
+        .max_nesting_depth(2)
+        .max_inline_nesting(1)
+        .max_document_size(input.len() / 2)
+        .max_link_references(1);
+    MarkdownParser::new(input).with_config(config).read_all();
+}
+
+fn stream(input: &[u8]) {
+    for _ in StreamingParser::new(io::Cursor::new(input.to_vec())) {}
+}
+
+fn render(input: &[u8]) {
+    let doc = MarkdownParser::new(input).read_document();
+
+    markdown::to_markdown(&doc.blocks, &doc.link_map);
+    ansi::to_ansi(&doc.blocks);
+    ansi::to_ansi_with_style(&doc.blocks, AnsiStyle::default().width(1).colors(false));
+    latex::to_latex(&doc.blocks);
+    plain::to_plain_text(&doc.blocks);
+    plain::to_plain_text_with_style(&doc.blocks, PlainStyle::default().limit(1));
+    roff::to_roff(&doc.blocks);
+    xml::to_xml(&doc.blocks);
+
+    let (doc, spans) = MarkdownParser::new(input).read_all_with_spans();
+    xml::to_xml_with_sourcepos(&doc, spans.as_slice());
+}
+
+// xorshift, good enough to pick mutations
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 { 0 } else { (self.next() % n as u64) as usize }
+    }
+}
+
+// applies a few random edits to a copy of the seed
+fn mutate(seed: &[u8], rng: &mut Random) -> Vec<u8> {
+    let mut input = seed.to_vec();
+    for _ in 0..1 + rng.below(8) {
+        let pos = rng.below(input.len() + 1);
+        match rng.below(5) {
+            0 => {
+                let b = SPECIAL[rng.below(SPECIAL.len())];
+                input.insert(pos, b);
+            }
+            1 => input.insert(pos, rng.next() as u8),
+            2 if pos < input.len() => { input.remove(pos); }
+            3 => {
+                // repeat a special byte, to get deep nesting and long runs
+                let b = SPECIAL[rng.below(SPECIAL.len())];
+                for _ in 0..rng.below(64) {
+                    input.insert(pos, b);
+                }
+            }
+            _ if !input.is_empty() => {
+                // copy a piece of the input to another place
+                let start = rng.below(input.len());
+                let end = start + rng.below(input.len() - start + 1);
+                let piece = input[start..end].to_vec();
+                for (i, &b) in piece.iter().enumerate() {
+                    input.insert(pos + i, b);
+                }
+            }
+            _ => {}
+        }
+    }
+    input
+}
+
+// runs the target in its own thread, so a panic is caught and reported
+fn check(name: &'static str, target: fn(&[u8]), input: &[u8]) -> bool {
+    let input = input.to_vec();
+    match thread::spawn(move || target(input.as_slice())).join() {
+        Ok(()) => true,
+        Err(_) => {
+            let _ = writeln!(&mut io::stderr(), "target {} panicked", name);
+            false
+        }
+    }
+}
+
+fn read_dir(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut result = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return result
+    };
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue
+        };
+        let mut buf = Vec::new();
+        if File::open(&path).and_then(|mut f| f.read_to_end(&mut buf)).is_ok() {
+            result.push((path, buf));
+        }
+    }
+    result.sort_by(|a, b| a.0.cmp(&b.0));
+    result
+}
+
+fn save_crash(dir: &Path, n: usize, input: &[u8]) {
+    let path = dir.join(format!("crash-{}.md", n));
+    let r = fs::create_dir_all(dir)
+        .and_then(|_| File::create(&path))
+        .and_then(|mut f| f.write_all(input));
+    match r {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(e) => { let _ = writeln!(&mut io::stderr(), "can't save {}: {}", path.display(), e); }
+    }
+}
+
+fn usage_error(message: String) -> i32 {
+    let _ = writeln!(&mut io::stderr(), "fuzz: {}\n\n{}", message, USAGE);
+    1
+}
+
+fn run(args: &[String]) -> i32 {
+    let mut iterations = 10000;
+    let mut seed = 1;
+    let mut replay_only = false;
+    let mut targets: Vec<(&'static str, fn(&[u8]))> = TARGETS.iter().map(|&t| t).collect();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_slice() {
+            "--help" | "-h" => { print!("{}", USAGE); return 0 }
+            "--replay" => replay_only = true,
+            "-n" | "-s" => {
+                let value = match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(value) => value,
+                    None => return usage_error(format!("{} needs a number", arg))
+                };
+                if arg.as_slice() == "-n" { iterations = value as usize } else { seed = value }
+            }
+            "all" => targets = TARGETS.iter().map(|&t| t).collect(),
+            name => match TARGETS.iter().find(|t| t.0 == name) {
+                Some(&target) => targets = vec![target],
+                None => return usage_error(format!("unknown target {}", name))
+            }
+        }
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let crashes = root.join("crashes");
+    let mut seeds = read_dir(&root.join("regressions"));
+    seeds.extend(read_dir(&root.join("..").join("examples").join("data")).into_iter());
+
+    let mut failures = 0;
+    for &(name, target) in targets.iter() {
+        for &(ref path, ref input) in seeds.iter() {
+            if !check(name, target, input.as_slice()) {
+                let _ = writeln!(&mut io::stderr(), "  on {}", path.display());
+                failures += 1;
+            }
+        }
+    }
+    println!("replayed {} inputs, {} failures", seeds.len() * targets.len(), failures);
+    if replay_only || seeds.is_empty() {
+        return if failures == 0 { 0 } else { 2 };
+    }
+
+    // xorshift never leaves zero
+    let mut rng = Random(if seed == 0 { 1 } else { seed });
+    for i in 0..iterations {
+        let input = mutate(seeds[rng.below(seeds.len())].1.as_slice(), &mut rng);
+        for &(name, target) in targets.iter() {
+            if !check(name, target, input.as_slice()) {
+                save_crash(&crashes, i, input.as_slice());
+                failures += 1;
+            }
+        }
+    }
+    println!("ran {} random inputs, {} failures", iterations, failures);
+
+    if failures == 0 { 0 } else { 2 }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(run(&args));
+}
@@ -0,0 +1,167 @@
+use std::str;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use md::{MarkdownParser, Document, FixLinks};
+use md::render::markdown::{self, MarkdownStyle, HeadingStyle, ListNumbering, LinkStyle};
+
+const USAGE: &'static str = "\
+Usage: mdrender fmt [options] [files...]
+
+Formats Markdown files in place. Without files, or with `-`, formats the
+standard input to the standard output.
+
+Options:
+    --check                 do not write anything, exit with 1 if any file
+                            is not formatted
+    --heading atx|setext    heading style (default: atx)
+    --bullet -|*|+          bullet character (default: -)
+    --emphasis *|_          emphasis marker (default: *)
+    --wrap N                wrap paragraphs at N columns, 0 to keep line
+                            breaks (default: 0)
+    --numbering inc|ones    ordered list numbering (default: inc)
+    --links preserve|inline|reference
+                            link style (default: preserve)
+";
+
+pub fn run(args: &[String]) -> i32 {
+    let mut style = MarkdownStyle::default();
+    let mut check = false;
+    let mut files = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_slice() {
+            "--help" | "-h" => { print!("{}", USAGE); return 0 }
+            "--check" => check = true,
+            "--heading" | "--bullet" | "--emphasis" | "--wrap" | "--numbering" | "--links" => {
+                let value = match args.next() {
+                    Some(value) => value.as_slice(),
+                    None => return usage_error(format!("missing value for {}", arg))
+                };
+                style = match set_option(style, arg.as_slice(), value) {
+                    Some(style) => style,
+                    None => return usage_error(format!("invalid value for {}: {}", arg, value))
+                };
+            }
+            s if s.starts_with("-") && s != "-" => return usage_error(format!("unknown option: {}", s)),
+            _ => files.push(arg.clone())
+        }
+    }
+
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut code = 0;
+    for file in files.iter() {
+        let r = if file.as_slice() == "-" {
+            format_stdin(style, check)
+        } else {
+            format_file(file.as_slice(), style, check)
+        };
+        match r {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("{}: not formatted", file);
+                if code == 0 { code = 1; }
+            }
+            Err(e) => {
+                let _ = writeln!(&mut io::stderr(), "{}: {}", file, e);
+                code = 2;
+            }
+        }
+    }
+    code
+}
+
+fn set_option(style: MarkdownStyle, name: &str, value: &str) -> Option<MarkdownStyle> {
+    Some(match (name, value) {
+        ("--heading", "atx") => style.heading_style(HeadingStyle::Atx),
+        ("--heading", "setext") => style.heading_style(HeadingStyle::Setext),
+        ("--bullet", "-") | ("--bullet", "*") | ("--bullet", "+") =>
+            style.bullet(value.chars().next().unwrap()),
+        ("--emphasis", "*") | ("--emphasis", "_") =>
+            style.emphasis(value.chars().next().unwrap()),
+        ("--wrap", _) => match value.parse() {
+            Ok(width) => style.wrap_width(width),
+            Err(_) => return None
+        },
+        ("--numbering", "inc") => style.numbering(ListNumbering::Incrementing),
+        ("--numbering", "ones") => style.numbering(ListNumbering::AllOnes),
+        ("--links", "preserve") => style.link_style(LinkStyle::Preserve),
+        ("--links", "inline") => style.link_style(LinkStyle::Inline),
+        ("--links", "reference") => style.link_style(LinkStyle::Reference),
+        _ => return None
+    })
+}
+
+fn usage_error(message: String) -> i32 {
+    let _ = writeln!(&mut io::stderr(), "{}\n\n{}", message, USAGE);
+    2
+}
+
+pub fn format(input: &[u8], style: MarkdownStyle) -> Result<String, String> {
+    if str::from_utf8(input).is_err() {
+        return Err("input is not valid UTF-8".to_string());
+    }
+
+    let mut p = MarkdownParser::new(input);
+    let mut doc: Document = p.by_ref().collect();
+    doc.fix_links(&*p.link_map());
+    let output = markdown::to_markdown_with_style(&doc, &*p.link_map(), style);
+
+    // files are rewritten in place, so the output must mean the same
+    let mut q = MarkdownParser::new(output.as_bytes());
+    let mut reparsed: Document = q.by_ref().collect();
+    reparsed.fix_links(&*q.link_map());
+    if reparsed != doc {
+        return Err("formatting would change the document, left as is".to_string());
+    }
+    Ok(output)
+}
+
+// returns whether the input was already formatted
+fn format_stdin(style: MarkdownStyle, check: bool) -> Result<bool, String> {
+    let mut input = Vec::new();
+    try!(io::stdin().read_to_end(&mut input).map_err(|e| e.to_string()));
+    let output = try!(format(input.as_slice(), style));
+    if !check {
+        try!(io::stdout().write_all(output.as_bytes()).map_err(|e| e.to_string()));
+    }
+    Ok(output.as_bytes() == input.as_slice())
+}
+
+fn format_file(path: &str, style: MarkdownStyle, check: bool) -> Result<bool, String> {
+    let mut input = Vec::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_end(&mut input)).map_err(|e| e.to_string()));
+    let output = try!(format(input.as_slice(), style));
+
+    let formatted = output.as_bytes() == input.as_slice();
+    if !formatted && !check {
+        try!(File::create(path).and_then(|mut f| f.write_all(output.as_bytes())).map_err(|e| e.to_string()));
+        return Ok(true);
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use md::render::markdown::MarkdownStyle;
+
+    use super::format;
+
+    #[test]
+    fn nested_tight_lists_stay_tight() {
+        for source in ["- a\n  - b\n  - c\n- d\n", "1. a\n   - b\n2. c\n"].iter() {
+            assert_eq!(format(source.as_bytes(), MarkdownStyle::default()), Ok(source.to_string()));
+        }
+    }
+
+    #[test]
+    fn formatted_output_is_stable() {
+        let source = "* a\n    * b\n\n* c\n\nText\n====\n";
+        let once = format(source.as_bytes(), MarkdownStyle::default()).unwrap();
+        assert_eq!(format(once.as_bytes(), MarkdownStyle::default()), Ok(once.clone()));
+    }
+}
@@ -1,3 +1,27 @@
+extern crate md;
+
+use std::env;
+use std::process;
+
+mod fmt;
+mod render;
+
+const USAGE: &'static str = "\
+Usage: mdrender [options] [files...]
+       mdrender fmt [options] [files...]
+
+Commands:
+    fmt     format Markdown files, see `mdrender fmt --help`
+
+Without a command, renders Markdown files, see `mdrender --help`.
+";
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(|s| s.as_slice()) {
+        Some("fmt") => fmt::run(&args[1..]),
+        Some("--help") | Some("-h") => { print!("{}\n{}", USAGE, render::USAGE); 0 }
+        _ => render::run(&args)
+    };
+    process::exit(code);
 }
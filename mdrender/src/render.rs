@@ -0,0 +1,130 @@
+use std::env;
+use std::str;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use md::{MarkdownParser, ParsedDocument, LinkMap};
+use md::html;
+use md::render::{markdown, plain, ansi, roff};
+use md::render::ansi::AnsiStyle;
+use md::render::roff::RoffStyle;
+
+pub const USAGE: &'static str = "\
+Usage: mdrender [options] [files...]
+
+Renders Markdown files to the standard output. Without files, or with `-`,
+renders the standard input.
+
+Options:
+    --from markdown|html    input format (default: markdown)
+    --format ansi|plain|markdown|roff
+                            output format (default: ansi)
+    --width N               wrap paragraphs at N columns, 0 to keep line
+                            breaks (default: 80)
+    --no-color              do not emit escape sequences; also implied by the
+                            NO_COLOR environment variable
+    --title TITLE           for roff, start the page with a title line
+    --section N             for roff, the manual section (default: 1)
+";
+
+#[derive(Copy, Clone)]
+enum Format {
+    Ansi,
+    Plain,
+    Markdown,
+    Roff
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let mut from_html = false;
+    let mut format = Format::Ansi;
+    let mut style = AnsiStyle::default().colors(env::var_os("NO_COLOR").is_none());
+    let mut roff_style = RoffStyle::default();
+    let mut files = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_slice() {
+            "--no-color" => style = style.colors(false),
+            "--from" | "--format" | "--width" | "--title" | "--section" => {
+                let value = match args.next() {
+                    Some(value) => value.as_slice(),
+                    None => return usage_error(format!("missing value for {}", arg))
+                };
+                match (arg.as_slice(), value) {
+                    ("--from", "markdown") => from_html = false,
+                    ("--from", "html") => from_html = true,
+                    ("--format", "ansi") => format = Format::Ansi,
+                    ("--format", "plain") => format = Format::Plain,
+                    ("--format", "markdown") => format = Format::Markdown,
+                    ("--format", "roff") => format = Format::Roff,
+                    ("--title", _) => roff_style = roff_style.title(Some(value.to_string())),
+                    ("--section", _) => roff_style = roff_style.section(value.to_string()),
+                    ("--width", _) => match value.parse() {
+                        Ok(width) => style = style.width(width),
+                        Err(_) => return usage_error(format!("invalid value for {}: {}", arg, value))
+                    },
+                    _ => return usage_error(format!("invalid value for {}: {}", arg, value))
+                }
+            }
+            s if s.starts_with("-") && s != "-" => return usage_error(format!("unknown option: {}", s)),
+            _ => files.push(arg.clone())
+        }
+    }
+
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut code = 0;
+    for file in files.iter() {
+        let mut input = Vec::new();
+        let r = if file.as_slice() == "-" {
+            io::stdin().read_to_end(&mut input)
+        } else {
+            File::open(file.as_slice()).and_then(|mut f| f.read_to_end(&mut input))
+        };
+        let output = r.map_err(|e| e.to_string())
+            .and_then(|_| render(input.as_slice(), from_html, format, style, roff_style.clone()));
+        match output {
+            Ok(output) => {
+                let _ = io::stdout().write_all(output.as_bytes());
+            }
+            Err(e) => {
+                let _ = writeln!(&mut io::stderr(), "{}: {}", file, e);
+                code = 2;
+            }
+        }
+    }
+    code
+}
+
+fn usage_error(message: String) -> i32 {
+    let _ = writeln!(&mut io::stderr(), "{}\n\n{}", message, USAGE);
+    2
+}
+
+fn render(input: &[u8], from_html: bool, format: Format, style: AnsiStyle,
+          roff_style: RoffStyle) -> Result<String, String> {
+    let input = match str::from_utf8(input) {
+        Ok(input) => input,
+        Err(_) => return Err("input is not valid UTF-8".to_string())
+    };
+
+    let doc = if from_html {
+        ParsedDocument { blocks: html::from_html(input), link_map: LinkMap::new() }
+    } else {
+        MarkdownParser::new(input.as_bytes()).read_document()
+    };
+
+    Ok(match format {
+        Format::Ansi => ansi::to_ansi_with_style(&doc.blocks, style),
+        Format::Plain => {
+            let mut result = plain::to_plain_text(&doc.blocks);
+            result.push('\n');
+            result
+        }
+        Format::Markdown => markdown::to_markdown(&doc.blocks, &doc.link_map),
+        Format::Roff => roff::to_roff_with_style(&doc.blocks, roff_style)
+    })
+}
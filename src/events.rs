@@ -0,0 +1,357 @@
+//! A pull-based event interface to the token tree.
+//!
+//! `MarkdownParser::events()` yields a flat stream of `Start`/`End` events
+//! with text and other leaf events between them. Events of a block quote or a
+//! list are produced as the parser opens and closes it, so a large container
+//! does not have to be kept in memory. The block iterator of the parser is
+//! built on top of these events with `BlockBuilder`.
+//!
+//! `Events` turns any stream of blocks into events, and `Blocks` performs the
+//! reverse transformation. Filters can therefore rewrite events on the fly
+//! and turn them back into blocks.
+
+use std::collections::VecDeque;
+use std::borrow::Cow;
+
+use tokens::*;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Tag<'a> {
+    Heading(usize),
+    BlockQuote,
+    BlockCode(Option<Cow<'a, str>>),
+    /// Lists carry their start index and whether they are tight. Tightness is
+    /// known only when a list ends: a parser which emits events as it goes
+    /// sets it to `true` in `Start` and to the actual value in `End`.
+    OrderedList(usize, bool),
+    UnorderedList(bool),
+    Item,
+    Paragraph,
+    Emphasis,
+    MoreEmphasis,
+    Link {
+        link: Option<Cow<'a, str>>,
+        title: Option<Cow<'a, str>>,
+        id: Option<Cow<'a, str>>,
+        automatic: bool
+    },
+    Image {
+        link: Option<Cow<'a, str>>,
+        title: Option<Cow<'a, str>>,
+        id: Option<Cow<'a, str>>
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Event<'a> {
+    Start(Tag<'a>),
+    End(Tag<'a>),
+    Text(Cow<'a, str>),
+    Code(Cow<'a, str>),
+    /// A raw HTML block.
+    Html(Cow<'a, str>),
+    /// Raw HTML inside a paragraph or a heading.
+    InlineHtml(Cow<'a, str>),
+    SoftBreak,
+    LineBreak,
+    HorizontalRule
+}
+
+/// An iterator of events over an iterator of blocks.
+///
+/// Blocks are pulled from the underlying iterator one at a time, only when
+/// all events of the previous block have been consumed.
+pub struct Events<'a, I> {
+    blocks: I,
+    queue: VecDeque<Event<'a>>
+}
+
+impl<'a, I: Iterator<Item=Block<'a>>> Events<'a, I> {
+    #[inline]
+    pub fn new(blocks: I) -> Events<'a, I> {
+        Events {
+            blocks: blocks,
+            queue: VecDeque::new()
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item=Block<'a>>> Iterator for Events<'a, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Event<'a>> {
+        if self.queue.is_empty() {
+            match self.blocks.next() {
+                Some(block) => push_block_events(block, &mut self.queue),
+                None => return None
+            }
+        }
+        self.queue.pop_front()
+    }
+}
+
+/// Appends events of the block to the queue.
+pub fn push_block_events<'a>(block: Block<'a>, q: &mut VecDeque<Event<'a>>) {
+    match block {
+        Heading { level, content } => wrap(Tag::Heading(level), q, |q| push_text(content, q)),
+
+        BlockQuote(content) => wrap(Tag::BlockQuote, q, |q| push_document(content, q)),
+
+        BlockCode { tag, content } => wrap(Tag::BlockCode(tag), q, |q| q.push_back(Event::Text(content))),
+
+        HtmlBlock(s) => q.push_back(Event::Html(s)),
+
+        OrderedList { start_index, tight, items } =>
+            wrap(Tag::OrderedList(start_index, tight), q, |q| push_items(items, q)),
+
+        UnorderedList { tight, items } => wrap(Tag::UnorderedList(tight), q, |q| push_items(items, q)),
+
+        Paragraph(content) => wrap(Tag::Paragraph, q, |q| push_text(content, q)),
+
+        HorizontalRule => q.push_back(Event::HorizontalRule)
+    }
+}
+
+fn push_document<'a>(doc: Document<'a>, q: &mut VecDeque<Event<'a>>) {
+    for block in doc.into_iter() {
+        push_block_events(block, q);
+    }
+}
+
+fn push_items<'a>(items: Vec<Document<'a>>, q: &mut VecDeque<Event<'a>>) {
+    for item in items.into_iter() {
+        wrap(Tag::Item, q, |q| push_document(item, q));
+    }
+}
+
+fn push_text<'a>(text: Text<'a>, q: &mut VecDeque<Event<'a>>) {
+    for i in text.into_iter() {
+        push_inline(i, q);
+    }
+}
+
+fn push_inline<'a>(inline: Inline<'a>, q: &mut VecDeque<Event<'a>>) {
+    match inline {
+        LineBreak => q.push_back(Event::LineBreak),
+
+        // newlines inside chunks are soft breaks
+        Chunk(s) => if !s.contains("\n") {
+            q.push_back(Event::Text(s));
+        } else {
+            match s {
+                Cow::Borrowed(s) => push_lines(s, q, Cow::Borrowed),
+                Cow::Owned(s) => push_lines(s.as_slice(), q, |p: &str| Cow::Owned(p.to_string()))
+            }
+        },
+
+        Emphasis(content) => wrap(Tag::Emphasis, q, |q| push_text(content, q)),
+
+        MoreEmphasis(content) => wrap(Tag::MoreEmphasis, q, |q| push_text(content, q)),
+
+        Inline::Code(s) => q.push_back(Event::Code(s)),
+
+        HtmlInline(s) => q.push_back(Event::InlineHtml(s)),
+
+        Link { text, link, title, id } => {
+            let tag = Tag::Link { link: link, title: title, id: id, automatic: text.is_none() };
+            wrap(tag, q, |q| text.map(|t| push_text(t, q)).unwrap_or(()))
+        }
+
+        Image { alt, link, title, id } =>
+            wrap(Tag::Image { link: link, title: title, id: id }, q, |q| push_text(alt, q))
+    }
+}
+
+fn push_lines<'a, 's, F>(s: &'s str, q: &mut VecDeque<Event<'a>>, mut f: F)
+        where F: FnMut(&'s str) -> Cow<'a, str> {
+    let mut first = true;
+    for part in s.split('\n') {
+        if !first { q.push_back(Event::SoftBreak); }
+        first = false;
+        if !part.is_empty() { q.push_back(Event::Text(f(part))); }
+    }
+}
+
+#[inline]
+fn wrap<'a, F>(tag: Tag<'a>, q: &mut VecDeque<Event<'a>>, f: F) where F: FnOnce(&mut VecDeque<Event<'a>>) {
+    q.push_back(Event::Start(tag.clone()));
+    f(q);
+    q.push_back(Event::End(tag));
+}
+
+enum Frame<'a> {
+    Blocks(Document<'a>),
+    Items(Vec<Document<'a>>),
+    Inlines(Text<'a>),
+    Content(Cow<'a, str>)
+}
+
+/// Builds blocks from events fed one at a time.
+///
+/// Unbalanced `End` events and leaf events which are not allowed at their
+/// position are ignored.
+pub struct BlockBuilder<'a> {
+    stack: Vec<Frame<'a>>
+}
+
+impl<'a> BlockBuilder<'a> {
+    #[inline]
+    pub fn new() -> BlockBuilder<'a> {
+        BlockBuilder {
+            stack: Vec::new()
+        }
+    }
+
+    /// Feeds the next event; returns a top-level block once it is complete.
+    pub fn push(&mut self, event: Event<'a>) -> Option<Block<'a>> {
+        match event {
+            Event::Start(tag) => { self.start(&tag); None }
+            Event::End(tag) => self.end(tag),
+            Event::Text(s) => { self.push_inline(Chunk(s)); None }
+            Event::SoftBreak => { self.push_inline(Chunk(Cow::Borrowed("\n"))); None }
+            Event::LineBreak => { self.push_inline(LineBreak); None }
+            Event::Code(s) => { self.push_inline(Inline::Code(s)); None }
+            Event::Html(s) => self.push_block(HtmlBlock(s)),
+            Event::InlineHtml(s) => { self.push_inline(HtmlInline(s)); None }
+            Event::HorizontalRule => self.push_block(HorizontalRule)
+        }
+    }
+
+    /// Opens an element; its contents are collected until the matching `end()`.
+    pub fn start(&mut self, tag: &Tag<'a>) {
+        let frame = match *tag {
+            Tag::BlockQuote | Tag::Item => Frame::Blocks(Vec::new()),
+            Tag::OrderedList(..) | Tag::UnorderedList(_) => Frame::Items(Vec::new()),
+            Tag::BlockCode(_) => Frame::Content(Cow::Borrowed("")),
+            _ => Frame::Inlines(Vec::new())
+        };
+        self.stack.push(frame);
+    }
+
+    /// Closes the innermost element; returns it if it is a top-level block.
+    pub fn end(&mut self, tag: Tag<'a>) -> Option<Block<'a>> {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return None
+        };
+
+        // inline elements are added to their parent directly
+        let block = match (tag, frame) {
+            (Tag::Emphasis, Frame::Inlines(t)) => { self.push_inline(Emphasis(t)); return None }
+            (Tag::MoreEmphasis, Frame::Inlines(t)) => { self.push_inline(MoreEmphasis(t)); return None }
+            (Tag::Link { link, title, id, automatic }, Frame::Inlines(t)) => {
+                self.push_inline(Link {
+                    text: if automatic { None } else { Some(t) },
+                    link: link,
+                    title: title,
+                    id: id
+                });
+                return None
+            }
+            (Tag::Image { link, title, id }, Frame::Inlines(t)) => {
+                self.push_inline(Image { alt: t, link: link, title: title, id: id });
+                return None
+            }
+
+            (Tag::Item, Frame::Blocks(d)) => {
+                match self.stack.last_mut() {
+                    Some(&mut Frame::Items(ref mut items)) => items.push(d),
+                    _ => {}
+                }
+                return None
+            }
+
+            (Tag::Heading(level), Frame::Inlines(t)) => Heading { level: level, content: t },
+            (Tag::Paragraph, Frame::Inlines(t)) => Paragraph(t),
+            (Tag::BlockQuote, Frame::Blocks(d)) => BlockQuote(d),
+            (Tag::BlockCode(tag), Frame::Content(s)) => BlockCode { tag: tag, content: s },
+            // tightness is taken from End, see Tag
+            (Tag::OrderedList(start, tight), Frame::Items(items)) =>
+                OrderedList { start_index: start, tight: tight, items: items },
+            (Tag::UnorderedList(tight), Frame::Items(items)) =>
+                UnorderedList { tight: tight, items: items },
+
+            // mismatched tag, drop the frame
+            _ => return None
+        };
+
+        self.push_block(block)
+    }
+
+    /// Adds a complete block; returns it back if it is a top-level one.
+    pub fn push_block(&mut self, block: Block<'a>) -> Option<Block<'a>> {
+        match self.stack.last_mut() {
+            None => Some(block),
+            Some(&mut Frame::Blocks(ref mut d)) => { d.push(block); None }
+            Some(_) => None
+        }
+    }
+
+    fn push_inline(&mut self, inline: Inline<'a>) {
+        match self.stack.last_mut() {
+            Some(&mut Frame::Inlines(ref mut t)) => {
+                // adjacent chunks are merged
+                let merge = match (t.last(), &inline) {
+                    (Some(&Chunk(_)), &Chunk(_)) => true,
+                    _ => false
+                };
+                if !merge { return t.push(inline) }
+                if let (Some(&mut Chunk(ref mut s)), Chunk(s1)) = (t.last_mut(), inline) {
+                    append(s, s1);
+                }
+            }
+            Some(&mut Frame::Content(ref mut s)) => match inline {
+                Chunk(s1) => append(s, s1),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/// An iterator of blocks built from an iterator of events.
+pub struct Blocks<'a, I> {
+    events: I,
+    builder: BlockBuilder<'a>
+}
+
+impl<'a, I: Iterator<Item=Event<'a>>> Blocks<'a, I> {
+    #[inline]
+    pub fn new(events: I) -> Blocks<'a, I> {
+        Blocks {
+            events: events,
+            builder: BlockBuilder::new()
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item=Event<'a>>> Iterator for Blocks<'a, I> {
+    type Item = Block<'a>;
+
+    fn next(&mut self) -> Option<Block<'a>> {
+        loop {
+            let event = match self.events.next() {
+                Some(event) => event,
+                None => return None
+            };
+            if let Some(block) = self.builder.push(event) {
+                return Some(block);
+            }
+        }
+    }
+}
+
+/// Builds a document from a stream of events.
+#[inline]
+pub fn build_document<'a, I: Iterator<Item=Event<'a>>>(events: I) -> Document<'a> {
+    Blocks::new(events).collect()
+}
+
+// borrowed text is only copied when something has to be appended to it
+fn append<'a>(s: &mut Cow<'a, str>, s1: Cow<'a, str>) {
+    if s.is_empty() {
+        *s = s1;
+    } else {
+        s.to_mut().push_str(&*s1);
+    }
+}
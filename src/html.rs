@@ -0,0 +1,626 @@
+//! HTML importer.
+//!
+//! Parses a subset of HTML into a document: headings, paragraphs, emphasis,
+//! code, preformatted blocks, block quotes, lists, links, images, rules and
+//! line breaks. Other elements, as well as comments, are kept as raw HTML
+//! tokens with their source text. Parsing is tolerant: unclosed elements are
+//! closed implicitly and stray end tags are ignored, much like browsers do.
+//!
+//! Together with the Markdown renderer this converts HTML to Markdown:
+//!
+//! ```ignore
+//! let doc = md::html::from_html(html);
+//! let markdown = md::render::markdown::to_markdown(&doc, &LinkMap::new());
+//! ```
+
+use std::char;
+use std::ascii::AsciiExt;
+use std::borrow::Cow;
+
+use tokens::*;
+
+pub fn from_html(source: &str) -> Document<'static> {
+    let nodes = Parser::new(source).parse();
+    let c = Converter { source: source };
+    let nodes: Vec<&Node> = nodes.iter().collect();
+    c.convert_blocks(nodes.as_slice())
+}
+
+enum Node {
+    Element {
+        name: String,
+        attrs: Vec<(String, String)>,
+        children: Vec<Node>,
+        // the source span of the whole element, including its end tag
+        start: usize,
+        end: usize
+    },
+    Text(String),
+    Comment {
+        start: usize,
+        end: usize
+    }
+}
+
+impl Node {
+    fn attr(&self, attr: &str) -> Option<&str> {
+        match *self {
+            Node::Element { ref attrs, .. } =>
+                attrs.iter().find(|&&(ref n, _)| n.as_slice() == attr).map(|&(_, ref v)| v.as_slice()),
+            _ => None
+        }
+    }
+}
+
+// elements which can't have contents
+const VOID_ELEMENTS: [&'static str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+    "source", "track", "wbr"
+];
+
+// elements whose contents are not parsed
+const RAW_TEXT_ELEMENTS: [&'static str; 4] = ["script", "style", "textarea", "title"];
+
+// elements which end a paragraph
+const BLOCK_ELEMENTS: [&'static str; 41] = [
+    "address", "article", "aside", "blockquote", "body", "canvas", "dd", "details", "dialog",
+    "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3",
+    "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "li", "main", "nav", "noscript",
+    "ol", "p", "pre", "script", "section", "style", "table", "ul", "video"
+];
+
+#[inline]
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.contains(&name)
+}
+
+// an element which is not closed yet
+struct Open {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+    start: usize
+}
+
+struct Parser<'s> {
+    source: &'s str,
+    pos: usize,
+    root: Vec<Node>,
+    stack: Vec<Open>
+}
+
+impl<'s> Parser<'s> {
+    fn new(source: &'s str) -> Parser<'s> {
+        Parser {
+            source: source,
+            pos: 0,
+            root: Vec::new(),
+            stack: Vec::new()
+        }
+    }
+
+    fn parse(mut self) -> Vec<Node> {
+        let source = self.source;
+        while self.pos < source.len() {
+            let rest = &source[self.pos..];
+            if rest.starts_with("<!--") {
+                let start = self.pos;
+                self.pos = match rest[4..].find("-->") {
+                    Some(i) => self.pos + 4 + i + 3,
+                    None => self.source.len()
+                };
+                let end = self.pos;
+                self.push_node(Node::Comment { start: start, end: end });
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                // doctype or processing instruction
+                self.pos = match rest.find('>') {
+                    Some(i) => self.pos + i + 1,
+                    None => self.source.len()
+                };
+            } else if rest.starts_with("</") && starts_with_letter(&rest[2..]) {
+                self.parse_end_tag();
+            } else if rest.starts_with("<") && starts_with_letter(&rest[1..]) {
+                if !self.parse_start_tag() {
+                    // not a complete tag, treat it as text
+                    self.pos += 1;
+                    self.push_text("<");
+                }
+            } else {
+                let first = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(rest.len());
+                let n = match rest[first..].find('<') {
+                    Some(i) => first + i,
+                    None => rest.len()
+                };
+                self.pos += n;
+                self.push_text(decode_entities(&rest[..n]).as_slice());
+            }
+        }
+
+        let end = self.source.len();
+        self.close_to(0, end, end);
+        self.root
+    }
+
+    fn push_node(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            Some(open) => open.children.push(node),
+            None => self.root.push(node)
+        }
+    }
+
+    fn push_text(&mut self, s: &str) {
+        let children = match self.stack.last_mut() {
+            Some(open) => &mut open.children,
+            None => &mut self.root
+        };
+        if let Some(&mut Node::Text(ref mut t)) = children.last_mut() {
+            t.push_str(s);
+            return;
+        }
+        children.push(Node::Text(s.to_string()));
+    }
+
+    // closes elements from the top of the stack down to the given depth; the
+    // last of them ends at end, the others, closed implicitly, at implicit_end
+    fn close_to(&mut self, depth: usize, implicit_end: usize, end: usize) {
+        while self.stack.len() > depth {
+            let open = match self.stack.pop() {
+                Some(open) => open,
+                None => break
+            };
+            let node = Node::Element {
+                name: open.name,
+                attrs: open.attrs,
+                children: open.children,
+                start: open.start,
+                end: if self.stack.len() == depth { end } else { implicit_end }
+            };
+            self.push_node(node);
+        }
+    }
+
+    fn find_open(&self, name: &str) -> Option<usize> {
+        self.stack.iter().rposition(|open| open.name.as_slice() == name)
+    }
+
+    fn parse_end_tag(&mut self) {
+        let start = self.pos;
+        let source = self.source;
+        let rest = &source[self.pos + 2..];
+        let name_len = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(rest.len());
+        let name = rest[..name_len].to_lowercase();
+        self.pos = match rest.find('>') {
+            Some(i) => self.pos + 2 + i + 1,
+            None => self.source.len()
+        };
+
+        // end tags without an open element are ignored
+        if let Some(i) = self.find_open(name.as_slice()) {
+            let end = self.pos;
+            self.close_to(i, start, end);
+        }
+    }
+
+    // returns false if there is no complete tag at the current position
+    fn parse_start_tag(&mut self) -> bool {
+        let start = self.pos;
+        let source = self.source;
+        let mut pos = self.pos + 1;
+
+        let name_len = source[pos..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(source.len() - pos);
+        let name = source[pos..pos + name_len].to_lowercase();
+        pos += name_len;
+
+        let mut attrs = Vec::new();
+        let mut self_closing = false;
+        loop {
+            pos += source[pos..].find(|c: char| !c.is_whitespace()).unwrap_or(source.len() - pos);
+            let rest = &source[pos..];
+            if rest.is_empty() {
+                return false;
+            } else if rest.starts_with(">") {
+                pos += 1;
+                break;
+            } else if rest.starts_with("/>") {
+                pos += 2;
+                self_closing = true;
+                break;
+            } else if rest.starts_with("/") {
+                pos += 1;
+                continue;
+            }
+
+            let n = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .unwrap_or(rest.len());
+            let attr = rest[..n].to_lowercase();
+            pos += n;
+
+            let mut value = String::new();
+            let after = source[pos..].trim_left();
+            if after.starts_with("=") {
+                pos = source.len() - after.len() + 1;
+                let v = source[pos..].trim_left();
+                pos = source.len() - v.len();
+                let quote = v.chars().next();
+                match quote {
+                    Some(q) if q == '"' || q == '\'' => {
+                        match v[1..].find(q) {
+                            Some(i) => {
+                                value = decode_entities(&v[1..i + 1]);
+                                pos += i + 2;
+                            }
+                            None => return false
+                        }
+                    }
+                    _ => {
+                        let n = v.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(v.len());
+                        value = decode_entities(&v[..n]);
+                        pos += n;
+                    }
+                }
+            }
+            attrs.push((attr, value));
+        }
+        self.pos = pos;
+
+        // a new block ends an open paragraph, a new list item ends the
+        // previous item of the same list
+        if is_one_of(name.as_slice(), &BLOCK_ELEMENTS) {
+            if self.stack.last().map(|open| open.name.as_slice() == "p").unwrap_or(false) {
+                let depth = self.stack.len() - 1;
+                self.close_to(depth, start, start);
+            }
+        }
+        if name.as_slice() == "li" {
+            let list = self.stack.iter().rposition(|open| match open.name.as_slice() {
+                "ul" | "ol" => true,
+                _ => false
+            });
+            if let Some(i) = self.find_open("li") {
+                if list.map(|l| i > l).unwrap_or(true) {
+                    self.close_to(i, start, start);
+                }
+            }
+        }
+
+        if self_closing || is_one_of(name.as_slice(), &VOID_ELEMENTS) {
+            self.push_node(Node::Element {
+                name: name,
+                attrs: attrs,
+                children: Vec::new(),
+                start: start,
+                end: pos
+            });
+            return true;
+        }
+
+        let raw_text = is_one_of(name.as_slice(), &RAW_TEXT_ELEMENTS);
+        self.stack.push(Open {
+            name: name.clone(),
+            attrs: attrs,
+            children: Vec::new(),
+            start: start
+        });
+
+        if raw_text {
+            // the end tag is ASCII, so a match is at a character boundary
+            let close = format!("</{}", name);
+            let close = close.as_bytes();
+            let rest = source[pos..].as_bytes();
+            let n = rest.windows(close.len())
+                .position(|w| w.eq_ignore_ascii_case(close))
+                .unwrap_or(rest.len());
+            self.push_text(&source[pos..pos + n]);
+            self.pos = pos + n;
+        }
+        true
+    }
+}
+
+fn starts_with_letter(s: &str) -> bool {
+    s.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false)
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            if end > 10 { return None }
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                e if e.starts_with("#x") || e.starts_with("#X") =>
+                    u32::from_str_radix(&e[2..], 16).ok().and_then(char::from_u32),
+                e if e.starts_with("#") =>
+                    e[1..].parse().ok().and_then(char::from_u32),
+                _ => None
+            };
+            c.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// collapses whitespace runs to single spaces, like browsers do
+fn collapse_whitespace(s: &str) -> String {
+    let mut result = String::new();
+    let mut space = false;
+    for c in s.chars() {
+        if c.is_whitespace() && c != '\u{a0}' {
+            if !space {
+                result.push(' ');
+            }
+            space = true;
+        } else {
+            result.push(c);
+            space = false;
+        }
+    }
+    result
+}
+
+fn text_of(nodes: &[Node], out: &mut String) {
+    for node in nodes.iter() {
+        match *node {
+            Node::Text(ref s) => out.push_str(s.as_slice()),
+            Node::Element { ref children, .. } => text_of(children.as_slice(), out),
+            Node::Comment { .. } => {}
+        }
+    }
+}
+
+fn owned(s: &str) -> Cow<'static, str> {
+    Cow::Owned(s.to_string())
+}
+
+fn push_str(out: &mut Text<'static>, s: &str) {
+    if s.is_empty() { return }
+    if let Some(&mut Chunk(ref mut c)) = out.last_mut() {
+        c.to_mut().push_str(s);
+        return;
+    }
+    out.push(Chunk(owned(s)));
+}
+
+// removes whitespace at the start and the end of the text and around line breaks
+fn trim_text(text: Text<'static>) -> Text<'static> {
+    let n = text.len();
+    let mut result = Vec::new();
+    let mut after_break = true;
+    for (i, inline) in text.into_iter().enumerate() {
+        match inline {
+            Chunk(s) => {
+                let mut s = &*s;
+                if after_break { s = s.trim_left(); }
+                if i + 1 == n { s = s.trim_right(); }
+                push_str(&mut result, s);
+                after_break = false;
+            }
+            LineBreak => {
+                if let Some(&mut Chunk(ref mut s)) = result.last_mut() {
+                    let trimmed = s.trim_right().to_string();
+                    *s = Cow::Owned(trimmed);
+                }
+                result.push(LineBreak);
+                after_break = true;
+            }
+            other => {
+                result.push(other);
+                after_break = false;
+            }
+        }
+    }
+    result.retain(|i| match *i {
+        Chunk(ref s) => !s.is_empty(),
+        _ => true
+    });
+    result
+}
+
+struct Converter<'s> {
+    source: &'s str
+}
+
+impl<'s> Converter<'s> {
+    fn raw(&self, start: usize, end: usize) -> Cow<'static, str> {
+        owned(&self.source[start..end])
+    }
+
+    fn convert_blocks(&self, nodes: &[&Node]) -> Document<'static> {
+        let mut result = Vec::new();
+        let mut pending: Vec<&Node> = Vec::new();
+        for &node in nodes.iter() {
+            match *node {
+                Node::Element { ref name, .. } if is_one_of(name.as_slice(), &BLOCK_ELEMENTS) => {
+                    self.flush_paragraph(&mut pending, &mut result);
+                    self.convert_block(node, &mut result);
+                }
+                Node::Comment { start, end } if self.is_blank(pending.as_slice()) => {
+                    self.flush_paragraph(&mut pending, &mut result);
+                    result.push(HtmlBlock(self.raw(start, end)));
+                }
+                _ => pending.push(node)
+            }
+        }
+        self.flush_paragraph(&mut pending, &mut result);
+        result
+    }
+
+    fn is_blank(&self, nodes: &[&Node]) -> bool {
+        nodes.iter().all(|node| match **node {
+            Node::Text(ref s) => s.trim().is_empty(),
+            _ => false
+        })
+    }
+
+    // inline content between blocks becomes a paragraph
+    fn flush_paragraph(&self, pending: &mut Vec<&Node>, out: &mut Document<'static>) {
+        let text = self.convert_inlines(pending.as_slice());
+        pending.clear();
+        if !text.is_empty() {
+            out.push(Paragraph(text));
+        }
+    }
+
+    fn convert_block(&self, node: &Node, out: &mut Document<'static>) {
+        let (name, nodes, start, end) = match *node {
+            Node::Element { ref name, ref children, start, end, .. } => (name.as_slice(), children, start, end),
+            _ => return
+        };
+        let children: Vec<&Node> = nodes.iter().collect();
+
+        match name {
+            "html" | "body" => out.extend(self.convert_blocks(children.as_slice()).into_iter()),
+
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => out.push(Heading {
+                level: (name.as_bytes()[1] - b'0') as usize,
+                content: self.convert_inlines(children.as_slice())
+            }),
+
+            "p" => {
+                let content = self.convert_inlines(children.as_slice());
+                if !content.is_empty() {
+                    out.push(Paragraph(content));
+                }
+            }
+
+            "pre" => {
+                let mut content = String::new();
+                text_of(nodes.as_slice(), &mut content);
+                // a newline right after the start tag is not a part of the contents
+                if content.starts_with("\n") {
+                    content.remove(0);
+                }
+                if !content.is_empty() && !content.ends_with("\n") {
+                    content.push('\n');
+                }
+
+                // the language is usually a class of the inner code element
+                let tag = children.iter()
+                    .filter_map(|c| c.attr("class"))
+                    .chain(node.attr("class").into_iter())
+                    .flat_map(|classes| classes.split(' '))
+                    .filter_map(|c| if c.starts_with("language-") {
+                        Some(&c[9..])
+                    } else if c.starts_with("lang-") {
+                        Some(&c[5..])
+                    } else {
+                        None
+                    })
+                    .next();
+
+                out.push(BlockCode { tag: tag.map(owned), content: Cow::Owned(content) });
+            }
+
+            "blockquote" => out.push(BlockQuote(self.convert_blocks(children.as_slice()))),
+
+            "ul" | "ol" => {
+                let mut items = Vec::new();
+                // items of a loose list wrap their text in paragraphs
+                let mut tight = true;
+                for child in children.iter() {
+                    match **child {
+                        Node::Element { ref name, ref children, .. } if name.as_slice() == "li" => {
+                            tight = tight && !children.iter().any(|c| match *c {
+                                Node::Element { ref name, .. } => name.as_slice() == "p",
+                                _ => false
+                            });
+                            let children: Vec<&Node> = children.iter().collect();
+                            items.push(self.convert_blocks(children.as_slice()));
+                        }
+                        // whitespace between items
+                        _ => {}
+                    }
+                }
+                if name == "ol" {
+                    let start_index = node.attr("start").and_then(|s| s.trim().parse().ok()).unwrap_or(1);
+                    out.push(OrderedList { start_index: start_index, tight: tight, items: items });
+                } else {
+                    out.push(UnorderedList { tight: tight, items: items });
+                }
+            }
+
+            "hr" => out.push(HorizontalRule),
+
+            _ => out.push(HtmlBlock(self.raw(start, end)))
+        }
+    }
+
+    fn convert_inlines(&self, nodes: &[&Node]) -> Text<'static> {
+        let mut result = Vec::new();
+        for node in nodes.iter() {
+            self.convert_inline(*node, &mut result);
+        }
+        trim_text(result)
+    }
+
+    fn convert_children(&self, node: &Node) -> Text<'static> {
+        match *node {
+            Node::Element { ref children, .. } => {
+                let children: Vec<&Node> = children.iter().collect();
+                self.convert_inlines(children.as_slice())
+            }
+            _ => Vec::new()
+        }
+    }
+
+    fn convert_inline(&self, node: &Node, out: &mut Text<'static>) {
+        let (name, children, start, end) = match *node {
+            Node::Text(ref s) => return push_str(out, collapse_whitespace(s.as_slice()).as_slice()),
+            Node::Comment { start, end } => return out.push(HtmlInline(self.raw(start, end))),
+            Node::Element { ref name, ref children, start, end, .. } => (name.as_slice(), children, start, end)
+        };
+
+        match name {
+            "em" | "i" => out.push(Emphasis(self.convert_children(node))),
+
+            "strong" | "b" => out.push(MoreEmphasis(self.convert_children(node))),
+
+            "code" => {
+                let mut content = String::new();
+                text_of(children.as_slice(), &mut content);
+                out.push(Code(owned(content.replace("\n", " ").as_slice())));
+            }
+
+            "a" if node.attr("href").is_some() => out.push(Link {
+                text: Some(self.convert_children(node)),
+                link: node.attr("href").map(owned),
+                title: node.attr("title").map(owned),
+                id: None
+            }),
+
+            "img" => out.push(Image {
+                alt: node.attr("alt").map(|a| vec![Chunk(owned(a))]).unwrap_or(Vec::new()),
+                link: node.attr("src").map(owned),
+                title: node.attr("title").map(owned),
+                id: None
+            }),
+
+            "br" => out.push(LineBreak),
+
+            _ => out.push(HtmlInline(self.raw(start, end)))
+        }
+    }
+}
@@ -1,13 +1,19 @@
 #![feature(unsafe_destructor, core, collections)]
 
 #[macro_use] extern crate log;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(feature = "pandoc")] #[macro_use] extern crate serde_json;
 
 pub use tokens::*;
-pub use parser::MarkdownParser;
+pub use parser::{MarkdownParser, StreamingParser};
 
-mod util;
+#[macro_use] mod util;
 
 pub mod tokens;
 pub mod parser;
-
-
+pub mod events;
+pub mod visit;
+pub mod toc;
+pub mod render;
+pub mod html;
+#[cfg(feature = "pandoc")] pub mod pandoc;
@@ -0,0 +1,558 @@
+//! Conversion between documents and the Pandoc JSON AST, as used by Pandoc
+//! filters (`pandoc -t json` and `pandoc -f json`).
+//!
+//! Exporting is exact except that reference links are written with their
+//! resolved destinations. Pandoc elements without an equivalent in the token
+//! tree are lowered to the closest one when importing, e.g. `Strikeout` to its
+//! contents or `Math` to inline code, and each such element is reported.
+
+use std::fmt;
+use std::borrow::Cow;
+
+use serde_json::{self, Value};
+
+use tokens::*;
+
+/// The version of the Pandoc AST written by the exporter.
+pub const PANDOC_API_VERSION: [u64; 3] = [1, 23, 1];
+
+#[derive(Debug)]
+pub enum PandocError {
+    Json(serde_json::Error),
+    /// The JSON value is not a Pandoc document; the string describes what was
+    /// expected.
+    Malformed(String)
+}
+
+impl fmt::Display for PandocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PandocError::Json(ref e) => write!(f, "invalid JSON: {}", e),
+            PandocError::Malformed(ref what) => write!(f, "malformed Pandoc AST: expected {}", what)
+        }
+    }
+}
+
+pub type PandocResult<T> = Result<T, PandocError>;
+
+/// A Pandoc element which had no exact equivalent in the token tree.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Lowered {
+    /// The element type, like `Table` or `Strikeout`.
+    pub element: String,
+    /// What the element was converted to.
+    pub replacement: &'static str
+}
+
+pub fn to_pandoc_json<'a>(doc: &Document<'a>) -> String {
+    to_pandoc_value(doc).to_string()
+}
+
+pub fn to_pandoc_value<'a>(doc: &Document<'a>) -> Value {
+    json!({
+        "pandoc-api-version": PANDOC_API_VERSION.to_vec(),
+        "meta": {},
+        "blocks": export_blocks(doc)
+    })
+}
+
+/// Imports a Pandoc document. Returns the document and the elements which had
+/// to be lowered, in document order.
+pub fn from_pandoc_json(s: &str) -> PandocResult<(Document<'static>, Vec<Lowered>)> {
+    let value: Value = try!(serde_json::from_str(s).map_err(PandocError::Json));
+    from_pandoc_value(&value)
+}
+
+pub fn from_pandoc_value(value: &Value) -> PandocResult<(Document<'static>, Vec<Lowered>)> {
+    let mut importer = Importer { lowered: Vec::new() };
+    let blocks = try!(array(&value["blocks"], "a document with blocks"));
+    let doc = try!(importer.import_blocks(blocks));
+    Ok((doc, importer.lowered))
+}
+
+fn node(t: &str, c: Value) -> Value {
+    json!({ "t": t, "c": c })
+}
+
+fn empty_attr() -> Value {
+    json!(["", [], []])
+}
+
+fn export_blocks<'a>(doc: &Document<'a>) -> Vec<Value> {
+    doc.iter().map(export_block).collect()
+}
+
+fn export_block<'a>(block: &Block<'a>) -> Value {
+    match *block {
+        Heading { level, ref content } =>
+            node("Header", json!([level, empty_attr(), export_inlines(content)])),
+
+        BlockQuote(ref content) => node("BlockQuote", Value::Array(export_blocks(content))),
+
+        BlockCode { ref tag, ref content } => {
+            let classes: Vec<&str> = tag.iter().map(|t| &**t).collect();
+            node("CodeBlock", json!([["", classes, []], content.trim_right_matches('\n')]))
+        }
+
+        HtmlBlock(ref s) => node("RawBlock", json!(["html", &**s])),
+
+        OrderedList { start_index, tight, ref items } =>
+            node("OrderedList", json!([[start_index, {"t": "Decimal"}, {"t": "Period"}], export_items(items, tight)])),
+
+        UnorderedList { tight, ref items } => node("BulletList", Value::Array(export_items(items, tight))),
+
+        Paragraph(ref content) => node("Para", Value::Array(export_inlines(content))),
+
+        HorizontalRule => json!({ "t": "HorizontalRule" })
+    }
+}
+
+// paragraphs of tight list items are Plain elements
+fn export_items<'a>(items: &Vec<Document<'a>>, tight: bool) -> Vec<Value> {
+    items.iter().map(|item| {
+        let blocks = item.iter().map(|block| match *block {
+            Paragraph(ref content) if tight => node("Plain", Value::Array(export_inlines(content))),
+            ref block => export_block(block)
+        });
+        Value::Array(blocks.collect())
+    }).collect()
+}
+
+fn export_inlines<'a>(text: &Text<'a>) -> Vec<Value> {
+    let mut result = Vec::new();
+    for inline in text.iter() {
+        match *inline {
+            LineBreak => result.push(json!({ "t": "LineBreak" })),
+
+            Chunk(ref s) => export_chunk(&**s, &mut result),
+
+            Emphasis(ref content) => result.push(node("Emph", Value::Array(export_inlines(content)))),
+
+            MoreEmphasis(ref content) => result.push(node("Strong", Value::Array(export_inlines(content)))),
+
+            Code(ref s) => result.push(node("Code", json!([empty_attr(), &**s]))),
+
+            HtmlInline(ref s) => result.push(node("RawInline", json!(["html", &**s]))),
+
+            Link { text: None, ref link, .. } => {
+                let link = link.as_ref().map(|l| &**l).unwrap_or("");
+                result.push(node("Link", json!([["", ["uri"], []], [{"t": "Str", "c": link}], [link, ""]])));
+            }
+
+            Link { text: Some(ref text), ref link, ref title, .. } =>
+                result.push(node("Link", json!([
+                    empty_attr(),
+                    export_inlines(text),
+                    [link.as_ref().map(|l| &**l).unwrap_or(""), title.as_ref().map(|t| &**t).unwrap_or("")]
+                ]))),
+
+            Image { ref alt, ref link, ref title, .. } =>
+                result.push(node("Image", json!([
+                    empty_attr(),
+                    export_inlines(alt),
+                    [link.as_ref().map(|l| &**l).unwrap_or(""), title.as_ref().map(|t| &**t).unwrap_or("")]
+                ])))
+        }
+    }
+    result
+}
+
+// Pandoc splits text into words, spaces and soft breaks
+fn export_chunk(s: &str, out: &mut Vec<Value>) {
+    let mut word = String::new();
+    let mut space: Option<&'static str> = None;
+    for c in s.chars() {
+        if c == ' ' || c == '\n' {
+            if !word.is_empty() {
+                out.push(node("Str", Value::String(word.clone())));
+                word.clear();
+            }
+            space = match (space, c) {
+                (Some("SoftBreak"), _) | (_, '\n') => Some("SoftBreak"),
+                _ => Some("Space")
+            };
+        } else {
+            if let Some(t) = space.take() {
+                out.push(json!({ "t": t }));
+            }
+            word.push(c);
+        }
+    }
+    if let Some(t) = space {
+        out.push(json!({ "t": t }));
+    }
+    if !word.is_empty() {
+        out.push(node("Str", Value::String(word)));
+    }
+}
+
+fn array<'v>(v: &'v Value, what: &str) -> PandocResult<&'v Vec<Value>> {
+    v.as_array().ok_or_else(|| PandocError::Malformed(what.to_string()))
+}
+
+fn string<'v>(v: &'v Value, what: &str) -> PandocResult<&'v str> {
+    v.as_str().ok_or_else(|| PandocError::Malformed(what.to_string()))
+}
+
+fn number(v: &Value, what: &str) -> PandocResult<u64> {
+    v.as_u64().ok_or_else(|| PandocError::Malformed(what.to_string()))
+}
+
+fn tag(v: &Value) -> PandocResult<&str> {
+    string(&v["t"], "an element with a type")
+}
+
+// element contents are at index i of the `c` array
+fn arg(v: &Value, i: usize) -> &Value {
+    &v["c"][i]
+}
+
+// the class list of an attribute triple `[id, classes, key-value pairs]`
+fn classes(attr: &Value) -> PandocResult<Vec<&str>> {
+    let classes = try!(array(&attr[1], "an attribute with classes"));
+    let mut result = Vec::new();
+    for c in classes.iter() {
+        result.push(try!(string(c, "a class name")));
+    }
+    Ok(result)
+}
+
+fn owned(s: &str) -> Cow<'static, str> {
+    Cow::Owned(s.to_string())
+}
+
+fn non_empty(s: &str) -> Option<Cow<'static, str>> {
+    if s.is_empty() { None } else { Some(owned(s)) }
+}
+
+const BLOCK_TYPES: [&'static str; 15] = [
+    "Plain", "Para", "LineBlock", "CodeBlock", "RawBlock", "BlockQuote", "OrderedList",
+    "BulletList", "DefinitionList", "Header", "HorizontalRule", "Table", "Figure", "Div", "Null"
+];
+
+const INLINE_TYPES: [&'static str; 21] = [
+    "Str", "Emph", "Underline", "Strong", "Strikeout", "Superscript", "Subscript", "SmallCaps",
+    "Quoted", "Cite", "Code", "Space", "SoftBreak", "LineBreak", "Math", "RawInline", "Link",
+    "Image", "Note", "Span", "Null"
+];
+
+fn is_element_of(v: &Value, types: &[&str]) -> bool {
+    match v["t"].as_str() {
+        Some(t) => types.contains(&t),
+        None => false
+    }
+}
+
+// Pandoc marks tight lists with Plain instead of Para in their items
+fn is_tight(items: &Vec<Value>) -> bool {
+    items.iter().all(|item| match item.as_array() {
+        Some(blocks) => !blocks.iter().any(|b| is_element_of(b, &["Para"])),
+        None => true
+    })
+}
+
+struct Importer {
+    lowered: Vec<Lowered>
+}
+
+impl Importer {
+    fn lower(&mut self, element: &str, replacement: &'static str) {
+        self.lowered.push(Lowered { element: element.to_string(), replacement: replacement });
+    }
+
+    fn import_blocks(&mut self, blocks: &Vec<Value>) -> PandocResult<Document<'static>> {
+        let mut result = Vec::new();
+        for b in blocks.iter() {
+            try!(self.import_block(b, &mut result));
+        }
+        Ok(result)
+    }
+
+    fn import_block(&mut self, b: &Value, out: &mut Document<'static>) -> PandocResult<()> {
+        let t = try!(tag(b));
+        match t {
+            "Plain" | "Para" => {
+                let content = try!(self.import_inlines(try!(array(&b["c"], "inline elements"))));
+                out.push(Paragraph(content));
+            }
+
+            "Header" => {
+                let level = try!(number(arg(b, 0), "a heading level")) as usize;
+                let content = try!(self.import_inlines(try!(array(arg(b, 2), "inline elements"))));
+                out.push(Heading { level: level, content: content });
+            }
+
+            "CodeBlock" => {
+                let classes = try!(classes(arg(b, 0)));
+                let content = try!(string(arg(b, 1), "code block contents"));
+                let mut content = content.to_string();
+                if !content.is_empty() {
+                    content.push('\n');
+                }
+                out.push(BlockCode {
+                    tag: classes.first().map(|c| owned(*c)),
+                    content: Cow::Owned(content)
+                });
+            }
+
+            "BlockQuote" => {
+                let content = try!(self.import_blocks(try!(array(&b["c"], "block elements"))));
+                out.push(BlockQuote(content));
+            }
+
+            "OrderedList" => {
+                let start = try!(number(&arg(b, 0)[0], "a list start number")) as usize;
+                let items = try!(array(arg(b, 1), "list items"));
+                let tight = is_tight(items);
+                let items = try!(self.import_items(items));
+                out.push(OrderedList { start_index: start, tight: tight, items: items });
+            }
+
+            "BulletList" => {
+                let items = try!(array(&b["c"], "list items"));
+                let tight = is_tight(items);
+                let items = try!(self.import_items(items));
+                out.push(UnorderedList { tight: tight, items: items });
+            }
+
+            "HorizontalRule" => out.push(HorizontalRule),
+
+            "LineBlock" => {
+                self.lower(t, "a paragraph with line breaks");
+                let mut content = Vec::new();
+                for (i, line) in try!(array(&b["c"], "lines")).iter().enumerate() {
+                    if i > 0 {
+                        content.push(LineBreak);
+                    }
+                    let line = try!(self.import_inlines(try!(array(line, "inline elements"))));
+                    content.extend(line.into_iter());
+                }
+                out.push(Paragraph(content));
+            }
+
+            "RawBlock" => {
+                let format = try!(string(arg(b, 0), "a raw block format"));
+                let content = try!(string(arg(b, 1), "raw block contents"));
+                if format == "html" {
+                    out.push(HtmlBlock(owned(content)));
+                } else {
+                    self.lower(t, "a code block tagged with the format");
+                    out.push(BlockCode { tag: Some(owned(format)), content: owned(content) });
+                }
+            }
+
+            "DefinitionList" => {
+                self.lower(t, "a bullet list of terms in strong emphasis");
+                let mut items = Vec::new();
+                for entry in try!(array(&b["c"], "definition list entries")).iter() {
+                    let term = try!(self.import_inlines(try!(array(&entry[0], "a term"))));
+                    let mut item = vec![Paragraph(vec![MoreEmphasis(term)])];
+                    for definition in try!(array(&entry[1], "definitions")).iter() {
+                        let blocks = try!(self.import_blocks(try!(array(definition, "block elements"))));
+                        item.extend(blocks.into_iter());
+                    }
+                    items.push(item);
+                }
+                out.push(UnorderedList { tight: false, items: items });
+            }
+
+            "Null" => {}
+
+            // Div, Table, Figure and elements from newer Pandoc versions are
+            // replaced with the blocks and text found inside them
+            _ => {
+                self.lower(t, "its contents");
+                try!(self.import_contents(&b["c"], out));
+            }
+        }
+        Ok(())
+    }
+
+    fn import_items(&mut self, items: &Vec<Value>) -> PandocResult<Vec<Document<'static>>> {
+        let mut result = Vec::new();
+        for item in items.iter() {
+            result.push(try!(self.import_blocks(try!(array(item, "block elements")))));
+        }
+        Ok(result)
+    }
+
+    // looks for block elements and runs of inline elements in an arbitrary value
+    fn import_contents(&mut self, v: &Value, out: &mut Document<'static>) -> PandocResult<()> {
+        match *v {
+            Value::Array(ref values) => {
+                if !values.is_empty() && values.iter().all(|i| is_element_of(i, &INLINE_TYPES)) {
+                    let content = try!(self.import_inlines(values));
+                    if !content.is_empty() {
+                        out.push(Paragraph(content));
+                    }
+                } else {
+                    for value in values.iter() {
+                        try!(self.import_contents(value, out));
+                    }
+                }
+            }
+            Value::Object(_) if is_element_of(v, &BLOCK_TYPES) => try!(self.import_block(v, out)),
+            Value::Object(ref fields) =>
+                for value in fields.values() {
+                    try!(self.import_contents(value, out));
+                },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn import_inlines(&mut self, inlines: &Vec<Value>) -> PandocResult<Text<'static>> {
+        let mut result = Vec::new();
+        for i in inlines.iter() {
+            try!(self.import_inline(i, &mut result));
+        }
+        Ok(result)
+    }
+
+    fn import_inline(&mut self, i: &Value, out: &mut Text<'static>) -> PandocResult<()> {
+        let t = try!(tag(i));
+        match t {
+            "Str" => push_str(out, try!(string(&i["c"], "a string"))),
+            "Space" => push_str(out, " "),
+            "SoftBreak" => push_str(out, "\n"),
+            "LineBreak" => out.push(LineBreak),
+
+            "Emph" => {
+                let content = try!(self.import_inlines(try!(array(&i["c"], "inline elements"))));
+                out.push(Emphasis(content));
+            }
+
+            "Strong" => {
+                let content = try!(self.import_inlines(try!(array(&i["c"], "inline elements"))));
+                out.push(MoreEmphasis(content));
+            }
+
+            "Code" => out.push(Code(owned(try!(string(arg(i, 1), "code contents"))))),
+
+            "Link" => {
+                let target = arg(i, 2);
+                let link = try!(string(&target[0], "a link destination"));
+                let title = try!(string(&target[1], "a link title"));
+                let text = try!(self.import_inlines(try!(array(arg(i, 1), "inline elements"))));
+
+                // automatic links are marked with a class and have their
+                // destination as text
+                let automatic = try!(classes(arg(i, 0))).iter().any(|c| *c == "uri" || *c == "email") &&
+                    text_content(&text).as_slice() == link;
+                out.push(Link {
+                    text: if automatic { None } else { Some(text) },
+                    link: Some(owned(link)),
+                    title: non_empty(title),
+                    id: None
+                });
+            }
+
+            "Image" => {
+                let target = arg(i, 2);
+                let link = try!(string(&target[0], "an image source"));
+                let title = try!(string(&target[1], "an image title"));
+                let alt = try!(self.import_inlines(try!(array(arg(i, 1), "inline elements"))));
+                out.push(Image {
+                    alt: alt,
+                    link: Some(owned(link)),
+                    title: non_empty(title),
+                    id: None
+                });
+            }
+
+            "Underline" => {
+                self.lower(t, "emphasis");
+                let content = try!(self.import_inlines(try!(array(&i["c"], "inline elements"))));
+                out.push(Emphasis(content));
+            }
+
+            "Strikeout" | "Superscript" | "Subscript" | "SmallCaps" => {
+                self.lower(t, "its contents");
+                let content = try!(self.import_inlines(try!(array(&i["c"], "inline elements"))));
+                push_all(out, content);
+            }
+
+            "Span" | "Cite" => {
+                self.lower(t, "its contents");
+                let content = try!(self.import_inlines(try!(array(arg(i, 1), "inline elements"))));
+                push_all(out, content);
+            }
+
+            "Quoted" => {
+                self.lower(t, "its contents in quotation marks");
+                let quote = match try!(tag(arg(i, 0))) {
+                    "SingleQuote" => "'",
+                    _ => "\""
+                };
+                let content = try!(self.import_inlines(try!(array(arg(i, 1), "inline elements"))));
+                push_str(out, quote);
+                push_all(out, content);
+                push_str(out, quote);
+            }
+
+            "Math" => {
+                self.lower(t, "inline code");
+                out.push(Code(owned(try!(string(arg(i, 1), "math contents")))));
+            }
+
+            "RawInline" => {
+                let format = try!(string(arg(i, 0), "a raw inline format"));
+                let content = try!(string(arg(i, 1), "raw inline contents"));
+                if format == "html" {
+                    out.push(HtmlInline(owned(content)));
+                } else {
+                    self.lower(t, "inline code");
+                    out.push(Code(owned(content)));
+                }
+            }
+
+            "Note" => {
+                self.lower(t, "its text in parentheses");
+                let mut blocks = Vec::new();
+                try!(self.import_contents(&i["c"], &mut blocks));
+                let mut text = String::new();
+                for block in blocks.iter() {
+                    if let Paragraph(ref content) = *block {
+                        if !text.is_empty() {
+                            text.push(' ');
+                        }
+                        text.push_str(text_content(content).as_slice());
+                    }
+                }
+                push_str(out, format!(" ({})", text).as_slice());
+            }
+
+            "Null" => {}
+
+            _ => {
+                self.lower(t, "its contents");
+                let mut blocks = Vec::new();
+                try!(self.import_contents(&i["c"], &mut blocks));
+                for block in blocks.into_iter() {
+                    if let Paragraph(content) = block {
+                        push_all(out, content);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// appends text, merging it with the preceding chunk
+fn push_str(out: &mut Text<'static>, s: &str) {
+    if let Some(&mut Chunk(ref mut c)) = out.last_mut() {
+        c.to_mut().push_str(s);
+        return;
+    }
+    out.push(Chunk(owned(s)));
+}
+
+fn push_all(out: &mut Text<'static>, text: Text<'static>) {
+    for inline in text.into_iter() {
+        match inline {
+            Chunk(s) => push_str(out, &*s),
+            other => out.push(other)
+        }
+    }
+}
@@ -1,13 +1,14 @@
 use parser::{MarkdownParser, ParseResult, Success, End, NoParse};
 use tokens::*;
 use parser::inline::InlineParser;
+use util::{ByteSliceOps, CharOps};
 
-pub trait AtxHeadingParser {
-    fn parse_atx_heading(&self) -> ParseResult<Block>;
+pub trait AtxHeadingParser<'a> {
+    fn parse_atx_heading(&self) -> ParseResult<Block<'a>>;
 }
 
-impl<'a> AtxHeadingParser for MarkdownParser<'a> {
-    fn parse_atx_heading(&self) -> ParseResult<Block> {
+impl<'a> AtxHeadingParser<'a> for MarkdownParser<'a> {
+    fn parse_atx_heading(&self) -> ParseResult<Block<'a>> {
         debug!(">> trying atx header");
         parse_or_ret!(self.try_read_char(b'#'));
         self.cur.prev();
@@ -39,7 +40,7 @@ impl<'a> AtxHeadingParser for MarkdownParser<'a> {
         // read the rest of the line
         debug!(">> reading rest of the line");
         self.read_line();
-        let buf = self.cur.slice_until_now_from(pm);  // without newline
+        let buf = self.cur.slice_to_now_from(pm).trim_right(|b: u8| b.is_line_end());
         debug!(">> header line: {:?}", buf);
 
         debug!(">> skipping ending hashes and spaces");
@@ -53,15 +54,15 @@ impl<'a> AtxHeadingParser for MarkdownParser<'a> {
         }
         while n > 0 {
             match buf[n-1] {
-                b' ' => n -= 1,
+                b' ' | b'\t' => n -= 1,
                 _ => break
             }
         }
 
         debug!(">> parsing header inline content");
         // parse header contents
-        let subp = self.fork(&buf[..n]);
-        let result = self.fix_links(subp.parse_inline());
+        let subp = self.fork(pm.pos, pm.pos + n);
+        let result = subp.parse_inline();
         debug!(">> parsed: {:?}", result);
 
         Success(Heading {
@@ -1,55 +1,89 @@
+use std::borrow::Cow;
+
 use parser::{MarkdownParser, ParseResult, Success, End, NoParse};
-use tokens::*;
+use util::{ByteSliceOps, CharOps};
 
-pub trait BlockCodeParser {
-    fn parse_block_code(&self) -> ParseResult<Block>;
+pub trait BlockCodeParser<'a> {
+    fn code_fence_start(&self) -> ParseResult<(usize, u8, usize)>;
+    fn code_fence_end(&self, fc: u8, n: usize) -> bool;
+    fn read_code_info(&self) -> Option<Cow<'a, str>>;
 }
 
 trait Ops {
-    fn block_code_prefix(&self) -> ParseResult<()>;
+    fn code_fence(&self) -> ParseResult<(usize, u8, usize)>;
 }
 
 impl<'a> Ops for MarkdownParser<'a> {
-    fn block_code_prefix(&self) -> ParseResult<()> {
-        let mut n = 0usize;
+    // returns indentation, fence character and fence length
+    fn code_fence(&self) -> ParseResult<(usize, u8, usize)> {
         let m = self.cur.mark();
-        loop {
-            if n == 4 { break };
-            parse_or_ret!(self.try_read_char(b' '));
-            n += 1
+
+        let indent = self.cur.skip_indent(4);
+        if indent == 4 { return NoParse }
+
+        let fc = match self.cur.next_byte() {
+            Some(c) if c == b'`' || c == b'~' => c,
+            Some(_) => return NoParse,
+            None => return End
+        };
+
+        let mut n = 1;
+        while self.try_read_char(fc).is_success() {
+            n += 1;
         }
+        if n < 3 { return NoParse }
+
         m.cancel();
-        Success(())
+        Success((indent, fc, n))
     }
 }
 
-impl<'a> BlockCodeParser for MarkdownParser<'a> {
-    fn parse_block_code(&self) -> ParseResult<Block> {
-        debug!(">> trying code block");
+impl<'a> BlockCodeParser<'a> for MarkdownParser<'a> {
+    // an opening fence; the info string after it is left unread
+    fn code_fence_start(&self) -> ParseResult<(usize, u8, usize)> {
+        debug!(">> trying code fence");
+        let m = self.cur.mark();
+        let (indent, fc, n) = parse_or_ret!(self.code_fence());
 
+        // info string of a backtick fence can't contain backticks
+        if fc == b'`' {
+            let rest = &self.cur.buf[self.cur.pos.get()..];
+            for &b in rest.iter().take_while(|&&b| !b.is_line_end()) {
+                if b == b'`' { return NoParse }
+            }
+        }
+        m.cancel();
+        Success((indent, fc, n))
+    }
+
+    // a closing fence for an opening fence of n characters fc, including
+    // the line end
+    fn code_fence_end(&self, fc: u8, n: usize) -> bool {
         let m = self.cur.mark();
-        parse_or_ret!(self.block_code_prefix());
-        m.reset();
-
-        let mut buf = Vec::new();
-        loop {
-            match self.block_code_prefix() {
-                NoParse => {  // no prefix, check for emptiness
-                    let m = self.cur.mark();
-                    match self.try_parse_empty_line() {
-                        // non-empty line without prefix or end of buffer
-                        NoParse | End => break,
-                        // empty line without prefix, add newline to the result
-                        _ => { m.cancel(); buf.push(b'\n'); }
-                    }
+        match self.code_fence() {
+            Success((_, cfc, cn)) if cfc == fc && cn >= n => {
+                self.skip_spaces();
+                if self.try_read_line_end().is_success() || !self.cur.available() {
+                    m.cancel();
+                    return true;
                 }
-                End => break,
-                // prefix is ok, read everything else
-                _ => { parse_or_break!(self.read_line_to(&mut buf)); }
+                false
             }
+            _ => false
         }
+    }
+
+    // reads the info string after an opening fence, its first word is the tag
+    fn read_code_info(&self) -> Option<Cow<'a, str>> {
+        let pm = self.cur.phantom_mark();
+        self.read_line();
+        let info = self.cur.slice(pm, self.cur.phantom_mark())
+            .trim_left([b' ', b'\t'].as_slice()).trim_right(|b: u8| b == b'\t' || b.is_space());
 
-        // TODO: handle UTF-8 decoding error
-        Success(BlockCode { tag: None, content: String::from_utf8(buf).unwrap() })
+        let tag = match info.iter().position(|&b| b == b' ' || b == b'\t') {
+            Some(i) => &info[..i],
+            None => info
+        };
+        if tag.is_empty() { None } else { Some(String::from_utf8_lossy(tag)) }
     }
 }
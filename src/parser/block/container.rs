@@ -0,0 +1,745 @@
+use std::mem;
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use parser::{MarkdownParser, Piece, ParseResult, PhantomMark, Success, NoParse};
+use tokens::*;
+use events::Tag;
+use util::{CharOps, decode_text};
+use parser::block::atx_heading::AtxHeadingParser;
+use parser::block::block_code::BlockCodeParser;
+use parser::block::misc::MiscParser;
+
+use self::ContainerKind::*;
+use self::ListKind::*;
+use self::Leaf::*;
+use self::Lines::*;
+
+// Blocks are parsed line by line with a stack of open containers, like in
+// the CommonMark reference implementation. Every line first continues the
+// open containers it has prefixes for, then may open new ones; the rest of it
+// belongs to a leaf block in the innermost container. A line which continues
+// a paragraph is added to it even if it lacks some prefixes (a lazy
+// continuation line).
+//
+// Each line is read once. Text of a leaf block is borrowed from the buffer
+// while its lines are contiguous there, that is, outside of containers, and
+// is copied otherwise. Containers are not collected: their start and end and
+// every closed leaf block go to the event queue right away.
+
+pub trait ContainerParser<'a> {
+    fn parse_line(&self, open: &mut OpenBlocks<'a>);
+    fn close_blocks(&self, open: &mut OpenBlocks<'a>);
+}
+
+pub struct OpenBlocks<'a> {
+    // the root container is always open
+    stack: Vec<Container>,
+    // the leaf block open in the innermost container, with offsets of its
+    // start and of the end of its last line
+    leaf: Leaf<'a>,
+    leaf_start: usize,
+    leaf_end: usize,
+    // the offset after the last character of the current line which is not a
+    // space, None for a blank line
+    line_end: Option<usize>,
+    // the end of the last closed block
+    last_end: usize
+}
+
+impl<'a> OpenBlocks<'a> {
+    pub fn new() -> OpenBlocks<'a> {
+        OpenBlocks {
+            stack: vec![Container::new(Root, 0, 0)],
+            leaf: LfNone,
+            leaf_start: 0,
+            leaf_end: 0,
+            line_end: None,
+            last_end: 0
+        }
+    }
+
+    fn start_leaf(&mut self, leaf: Leaf<'a>, start: usize) {
+        self.leaf = leaf;
+        self.leaf_start = start;
+        self.leaf_end = self.line_end.unwrap_or(start);
+    }
+
+    // a block starts in the innermost container after a blank line; in an
+    // item or between items, this makes the list loose
+    fn loosen(&mut self) {
+        let n = self.stack.len();
+        match self.stack[n - 1].kind {
+            // items are always inside lists
+            Item { .. } => self.stack[n - 2].tight = false,
+            List(_) => self.stack[n - 1].tight = false,
+            Root | Quote => {}
+        }
+    }
+
+    // returns whether the last line in the innermost container was blank and
+    // forgets blank lines, because a line which is not blank follows them
+    fn take_blank(&mut self) -> bool {
+        let blank = self.stack.last().unwrap().blank;
+        for c in self.stack.iter_mut() {
+            c.blank = false;
+        }
+        blank
+    }
+
+    // how many block quotes and lists there are among the first n containers
+    fn nesting(&self, n: usize) -> usize {
+        self.stack[..n].iter().filter(|c| match c.kind {
+            Quote | List(_) => true,
+            Root | Item { .. } => false
+        }).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.len() == 1 && match self.leaf {
+            LfNone => true,
+            _ => false
+        }
+    }
+}
+
+#[derive(Copy, PartialEq)]
+enum ListKind {
+    Ordered {
+        start: usize,
+        delim: u8
+    },
+    Unordered {
+        marker: u8
+    }
+}
+
+impl ListKind {
+    // whether an item of the given kind belongs to a list of this kind
+    fn continues(self, other: ListKind) -> bool {
+        match (self, other) {
+            (Ordered { delim: a, .. }, Ordered { delim: b, .. }) => a == b,
+            (Unordered { marker: a }, Unordered { marker: b }) => a == b,
+            _ => false
+        }
+    }
+}
+
+#[derive(Copy)]
+struct ListMarker {
+    kind: ListKind,
+    // columns from the line position to the item content
+    width: usize,
+    // whether the rest of the line is blank
+    empty: bool
+}
+
+impl ListMarker {
+    // only non-empty items, and ordered ones starting with 1, can
+    // interrupt a paragraph
+    fn can_interrupt(&self) -> bool {
+        !self.empty && match self.kind {
+            Ordered { start, .. } => start == 1,
+            Unordered { .. } => true
+        }
+    }
+}
+
+#[derive(Copy, PartialEq)]
+enum ContainerKind {
+    Root,
+    Quote,
+    List(ListKind),
+    Item {
+        width: usize,
+        // whether the item has no content yet
+        empty: bool
+    }
+}
+
+struct Container {
+    kind: ContainerKind,
+    // offsets of the container marker and of the end of it
+    start: usize,
+    marker_end: usize,
+    // whether the last line in the container is blank
+    blank: bool,
+    // for lists, whether no blank line separates items or blocks in them
+    tight: bool
+}
+
+impl Container {
+    fn new(kind: ContainerKind, start: usize, marker_end: usize) -> Container {
+        Container {
+            kind: kind,
+            start: start,
+            marker_end: marker_end,
+            blank: false,
+            tight: true
+        }
+    }
+
+    // the root has no tag, it is never opened or closed
+    fn tag<'a>(&self) -> Option<Tag<'a>> {
+        match self.kind {
+            Root => None,
+            Quote => Some(Tag::BlockQuote),
+            List(Ordered { start, .. }) => Some(Tag::OrderedList(start, self.tight)),
+            List(Unordered { .. }) => Some(Tag::UnorderedList(self.tight)),
+            Item { .. } => Some(Tag::Item)
+        }
+    }
+
+    #[inline]
+    fn is_list(&self) -> bool {
+        match self.kind {
+            List(_) => true,
+            _ => false
+        }
+    }
+
+    #[inline]
+    fn set_not_empty(&mut self) {
+        if let Item { ref mut empty, .. } = self.kind {
+            *empty = false;
+        }
+    }
+}
+
+enum Leaf<'a> {
+    LfNone,
+    LfParagraph(Lines),
+    // the length without trailing blank lines
+    LfIndentedCode(Lines, usize),
+    LfFencedCode {
+        indent: usize,
+        fc: u8,
+        n: usize,
+        tag: Option<Cow<'a, str>>,
+        lines: Lines
+    }
+}
+
+impl<'a> Leaf<'a> {
+    #[inline]
+    fn is_paragraph(&self) -> bool {
+        match *self {
+            LfParagraph(_) => true,
+            _ => false
+        }
+    }
+
+    #[inline]
+    fn is_indented_code(&self) -> bool {
+        match *self {
+            LfIndentedCode(..) => true,
+            _ => false
+        }
+    }
+
+    #[inline]
+    fn is_fenced_code(&self) -> bool {
+        match *self {
+            LfFencedCode { .. } => true,
+            _ => false
+        }
+    }
+}
+
+// text of a leaf block
+enum Lines {
+    // between two marks in the buffer
+    Contiguous(PhantomMark, PhantomMark),
+    // with the origin of the copied bytes
+    Copied(Vec<u8>, Vec<(usize, usize)>)
+}
+
+impl Lines {
+    #[inline]
+    fn new(pm: PhantomMark) -> Lines {
+        Contiguous(pm, pm)
+    }
+
+    fn len(&self) -> usize {
+        match *self {
+            Contiguous(start, end) => end.pos - start.pos,
+            Copied(ref buf, _) => buf.len()
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        match *self {
+            Contiguous(start, ref mut end) => end.pos = start.pos + len,
+            Copied(ref mut buf, _) => buf.truncate(len)
+        }
+    }
+}
+
+// copies lines, replacing line endings with LF unless they are kept
+fn copy_lines(s: &[u8], keep_line_endings: bool) -> Vec<u8> {
+    if keep_line_endings { return s.to_vec() }
+
+    let mut result = Vec::with_capacity(s.len());
+    for (i, &b) in s.iter().enumerate() {
+        match b {
+            b'\r' if s.get(i + 1) == Some(&b'\n') => {}
+            b'\r' => result.push(b'\n'),
+            b => result.push(b)
+        }
+    }
+    result
+}
+
+trait Ops<'a> {
+    fn block_quote_prefix(&self) -> ParseResult<()>;
+    fn list_marker(&self) -> ParseResult<ListMarker>;
+    fn at_blank_line(&self) -> bool;
+    fn interrupts_paragraph(&self) -> bool;
+    fn first_nonspace(&self) -> usize;
+    fn content_end(&self) -> Option<usize>;
+    fn open_container(&self, open: &mut OpenBlocks<'a>, kind: ContainerKind, start: usize);
+    fn close_containers(&self, open: &mut OpenBlocks<'a>, depth: usize);
+    fn close_leaf(&self, open: &mut OpenBlocks<'a>);
+    fn add_block(&self, open: &mut OpenBlocks<'a>, block: Block<'a>, start: usize, end: usize);
+    fn append_line(&self, lines: &mut Lines, strip: usize);
+    fn append_to_leaf(&self, open: &mut OpenBlocks<'a>, strip: usize);
+    fn paragraph_content(&self, lines: Lines) -> Option<Text<'a>>;
+    fn code_content(&self, lines: Lines) -> Cow<'a, str>;
+}
+
+impl<'a> Ops<'a> for MarkdownParser<'a> {
+    fn block_quote_prefix(&self) -> ParseResult<()> {
+        let m = self.cur.mark();
+        parse_or_ret!(self.try_skip_initial_spaces());
+        parse_or_ret!(self.try_read_char(b'>'));
+        // optional space, which may be a part of a tab
+        self.cur.skip_indent(1);
+        m.cancel();
+        Success(())
+    }
+
+    fn list_marker(&self) -> ParseResult<ListMarker> {
+        let m = self.cur.mark();
+        let start = self.cur.column();
+        parse_or_ret!(self.try_skip_initial_spaces());
+
+        let kind = match opt_ret_end!(self.cur.next_byte()) {
+            c if one_of!(c, b'-', b'+', b'*') => Unordered { marker: c },
+            c if c.is_numeric() => {
+                // at most 9 digits
+                let mut number = (c - b'0') as usize;
+                let mut digits = 1;
+                while digits < 9 {
+                    match self.cur.current_byte() {
+                        Some(d) if d.is_numeric() => {
+                            number = number * 10 + (d - b'0') as usize;
+                            digits += 1;
+                            self.cur.next();
+                        }
+                        _ => break
+                    }
+                }
+                match self.cur.next_byte() {
+                    Some(d) if d == b'.' || d == b')' => Ordered { start: number, delim: d },
+                    _ => return NoParse
+                }
+            }
+            _ => return NoParse
+        };
+
+        let empty = match self.cur.current_byte() {
+            Some(b' ') | Some(b'\t') => self.at_blank_line(),
+            Some(b'\n') | Some(b'\r') | None => true,
+            Some(_) => return NoParse
+        };
+
+        // content follows one to four columns of spaces; with more, it is
+        // indented code after one column
+        let marker_end = self.cur.column();
+        let spaces = self.cur.indent();
+        let padding = if empty || spaces > 4 { 1 } else { spaces };
+        self.cur.skip_indent(padding);
+
+        m.cancel();
+        Success(ListMarker {
+            kind: kind,
+            width: marker_end - start + padding,
+            empty: empty
+        })
+    }
+
+    fn at_blank_line(&self) -> bool {
+        let _m = self.cur.mark();
+        match self.try_parse_empty_line() {
+            NoParse => false,
+            _ => true
+        }
+    }
+
+    fn interrupts_paragraph(&self) -> bool {
+        self.lookahead(|| self.parse_atx_heading()) ||
+        self.lookahead(|| self.parse_horizontal_rule()) ||
+        self.lookahead(|| self.code_fence_start()) ||
+        self.lookahead(|| self.block_quote_prefix()) ||
+        self.lookahead(|| match self.list_marker() {
+            Success(ref marker) if marker.can_interrupt() => Success(()),
+            _ => NoParse
+        })
+    }
+
+    // the offset of the first byte at the cursor which is not a space
+    fn first_nonspace(&self) -> usize {
+        let buf = self.cur.buf;
+        let mut i = self.cur.pos.get();
+        while i < buf.len() && (buf[i] == b' ' || buf[i] == b'\t') {
+            i += 1;
+        }
+        i
+    }
+
+    fn content_end(&self) -> Option<usize> {
+        let buf = self.cur.buf;
+        let pos = self.cur.pos.get();
+        let line = &buf[pos..];
+        let len = line.iter().position(|&b| b.is_line_end()).unwrap_or(line.len());
+        line[..len].iter().rposition(|&b| b != b' ' && b != b'\t').map(|i| pos + i + 1)
+    }
+
+    // start is the offset of the container marker, the cursor is after it
+    fn open_container(&self, open: &mut OpenBlocks<'a>, kind: ContainerKind, start: usize) {
+        if open.stack.last().unwrap().blank {
+            open.loosen();
+        }
+        open.stack.last_mut().unwrap().set_not_empty();
+        let c = Container::new(kind, start, self.cur.pos.get());
+        if let Some(tag) = c.tag() {
+            self.enqueue(Piece::Start(tag, c.start));
+        }
+        open.stack.push(c);
+    }
+
+    // closes the open leaf block and the containers above the given depth
+    fn close_containers(&self, open: &mut OpenBlocks<'a>, depth: usize) {
+        if open.stack.len() <= depth { return }
+        self.close_leaf(open);
+
+        while open.stack.len() > depth {
+            let c = open.stack.pop().unwrap();
+            // a blank line at the end of a container is at the end of its parent
+            if c.blank {
+                open.stack.last_mut().unwrap().blank = true;
+            }
+            // a container ends with its last block, or with its marker if it
+            // is empty
+            if c.marker_end > open.last_end {
+                open.last_end = c.marker_end;
+            }
+            if let Some(tag) = c.tag() {
+                self.enqueue(Piece::End(tag, open.last_end));
+            }
+        }
+    }
+
+    fn close_leaf(&self, open: &mut OpenBlocks<'a>) {
+        let block = match mem::replace(&mut open.leaf, LfNone) {
+            LfNone => return,
+            LfParagraph(lines) => match self.paragraph_content(lines) {
+                Some(content) => Paragraph(content),
+                None => return
+            },
+            LfIndentedCode(mut lines, len) => {
+                lines.truncate(len);
+                BlockCode { tag: None, content: self.code_content(lines) }
+            }
+            LfFencedCode { tag, lines, .. } => BlockCode { tag: tag, content: self.code_content(lines) }
+        };
+        let (start, end) = (open.leaf_start, open.leaf_end);
+        self.add_block(open, block, start, end);
+    }
+
+    #[inline]
+    fn add_block(&self, open: &mut OpenBlocks<'a>, block: Block<'a>, start: usize, end: usize) {
+        open.last_end = end;
+        self.enqueue(Piece::Leaf(block, start, end));
+    }
+
+    // appends the rest of the line without strip columns of indentation
+    fn append_line(&self, lines: &mut Lines, strip: usize) {
+        let line_start = self.cur.phantom_mark();
+        let partial = self.cur.partial.get() > 0;
+        self.cur.skip_indent(strip);
+        let pm = self.cur.phantom_mark();
+
+        if let Contiguous(start, end) = *lines {
+            if pm == line_start && !partial && (start == end || end == pm) {
+                let start = if start == end { pm } else { start };
+                self.read_line();
+                *lines = Contiguous(start, self.cur.phantom_mark());
+                return;
+            }
+            let buf = copy_lines(self.cur.slice(start, end), self.config.keep_line_endings);
+            *lines = Copied(buf, vec![(0, self.source_pos(start.pos))]);
+        }
+
+        if let Copied(ref mut buf, ref mut origin) = *lines {
+            origin.push((buf.len(), self.source_pos(pm.pos)));
+            self.read_line_to(buf);
+        }
+    }
+
+    fn append_to_leaf(&self, open: &mut OpenBlocks<'a>, strip: usize) {
+        match open.leaf {
+            LfParagraph(ref mut lines) |
+            LfIndentedCode(ref mut lines, _) |
+            LfFencedCode { ref mut lines, .. } => self.append_line(lines, strip),
+            LfNone => { self.read_line(); return }
+        }
+        if let Some(end) = open.line_end {
+            open.leaf_end = end;
+        }
+    }
+
+    fn paragraph_content(&self, lines: Lines) -> Option<Text<'a>> {
+        match lines {
+            Contiguous(start, end) => self.fork(start.pos, end.pos).parse_paragraph_content(),
+            Copied(buf, origin) => {
+                // the buffer is local, so the result can't borrow from it
+                let subp = self.fork_with_origin(buf.as_slice(), Rc::new(origin), 0);
+                let content = subp.parse_paragraph_content();
+                content.map(|c| c.into_owned())
+            }
+        }
+    }
+
+    // invalid UTF-8 sequences are replaced
+    fn code_content(&self, lines: Lines) -> Cow<'a, str> {
+        match lines {
+            Contiguous(start, end) => {
+                let content = self.cur.slice(start, end);
+                if self.config.keep_line_endings { String::from_utf8_lossy(content) }
+                else { decode_text(content) }
+            }
+            Copied(buf, _) => Cow::Owned(match String::from_utf8(buf) {
+                Ok(s) => s,
+                Err(e) => String::from_utf8_lossy(e.into_bytes().as_slice()).into_owned()
+            })
+        }
+    }
+}
+
+impl<'a> ContainerParser<'a> for MarkdownParser<'a> {
+    fn parse_line(&self, open: &mut OpenBlocks<'a>) {
+        open.line_end = self.content_end();
+
+        // continue open containers
+        let mut matched = 1;
+        while matched < open.stack.len() {
+            let ok = match open.stack[matched].kind {
+                Root | List(_) => true,
+                Quote => self.block_quote_prefix().is_success(),
+                Item { width, empty } =>
+                    if self.at_blank_line() {
+                        // an item can start with at most one blank line
+                        !empty
+                    } else if self.cur.indent() >= width {
+                        self.cur.skip_indent(width);
+                        true
+                    } else {
+                        false
+                    }
+            };
+            if !ok { break }
+            matched += 1;
+        }
+        let all_matched = matched == open.stack.len();
+        debug!(">> matched {} of {} containers", matched, open.stack.len());
+
+        // open new containers, except in fenced code
+        let mut opened = false;
+        let in_fence = all_matched && open.leaf.is_fenced_code();
+        while !in_fence && !self.at_blank_line() && self.cur.indent() < 4 {
+            let in_paragraph = !opened && all_matched && open.leaf.is_paragraph();
+            if in_paragraph && self.lookahead(|| self.parse_setext_underline()) { break }
+            if self.lookahead(|| self.parse_horizontal_rule()) { break }
+
+            let start = self.first_nonspace();
+            let m = self.cur.mark();
+            let marker = if self.block_quote_prefix().is_success() {
+                None
+            } else {
+                match self.list_marker() {
+                    Success(marker) if !in_paragraph || marker.can_interrupt() => Some(marker),
+                    _ => break
+                }
+            };
+
+            // too deep containers are left to the leaf block; an item of a
+            // list which stays open does not nest deeper
+            let n = if opened { open.stack.len() } else { matched };
+            let deeper = match (marker, open.stack[n - 1].kind) {
+                (Some(_), List(_)) => false,
+                _ => true
+            };
+            if deeper && open.nesting(n) >= self.config.max_nesting_depth { break }
+            m.cancel();
+
+            // the open leaf block ends, and so do containers without prefixes
+            if !opened {
+                self.close_containers(open, matched);
+                self.close_leaf(open);
+                opened = true;
+            }
+
+            match marker {
+                None => self.open_container(open, Quote, start),
+                Some(marker) => {
+                    let list = match open.stack.last() {
+                        Some(&Container { kind: List(kind), .. }) => Some(kind),
+                        _ => None
+                    };
+                    match list {
+                        Some(kind) if kind.continues(marker.kind) => {}
+                        Some(_) => {
+                            // an item of another kind starts a new list
+                            let depth = open.stack.len() - 1;
+                            self.close_containers(open, depth);
+                            self.open_container(open, List(marker.kind), start);
+                        }
+                        None => self.open_container(open, List(marker.kind), start)
+                    }
+                    self.open_container(open, Item { width: marker.width, empty: marker.empty }, start);
+                }
+            }
+        }
+
+        let blank = self.at_blank_line();
+
+        if !opened && !all_matched {
+            if open.leaf.is_paragraph() && !blank && !self.interrupts_paragraph() {
+                debug!(">> lazy continuation line");
+                self.append_to_leaf(open, 0);
+                return;
+            }
+            self.close_containers(open, matched);
+        }
+
+        // a list contains only items, anything but a blank line closes it
+        if open.stack.last().unwrap().is_list() {
+            if blank {
+                open.stack.last_mut().unwrap().blank = true;
+                self.read_line();
+                return;
+            }
+            let depth = open.stack.len() - 1;
+            self.close_containers(open, depth);
+        }
+
+        // the rest of the line belongs to a leaf block
+        if blank {
+            // blank lines in fenced code and after a list marker do not count
+            if !opened && !open.leaf.is_fenced_code() {
+                open.stack.last_mut().unwrap().blank = true;
+            }
+            if open.leaf.is_paragraph() {
+                self.close_leaf(open);
+            }
+            let strip = match open.leaf {
+                LfIndentedCode(..) => 4,
+                LfFencedCode { indent, .. } => indent,
+                _ => 0
+            };
+            self.append_to_leaf(open, strip);
+            return;
+        }
+        open.stack.last_mut().unwrap().set_not_empty();
+        let after_blank = open.take_blank();
+        let start = self.first_nonspace();
+
+        // fenced code continues until the closing fence
+        let fence = match open.leaf {
+            LfFencedCode { indent, fc, n, .. } => Some((indent, fc, n)),
+            _ => None
+        };
+        if let Some((indent, fc, n)) = fence {
+            if self.code_fence_end(fc, n) {
+                open.leaf_end = open.line_end.unwrap_or(open.leaf_end);
+                self.close_leaf(open);
+            } else {
+                self.append_to_leaf(open, indent);
+            }
+            return;
+        }
+
+        if self.cur.indent() >= 4 {
+            // indented code can't interrupt a paragraph
+            if open.leaf.is_paragraph() {
+                self.append_to_leaf(open, 0);
+                return;
+            }
+            if !open.leaf.is_indented_code() {
+                self.close_leaf(open);
+                if after_blank { open.loosen(); }
+                open.start_leaf(LfIndentedCode(Lines::new(self.cur.phantom_mark()), 0), start);
+            }
+            if let LfIndentedCode(ref mut lines, ref mut len) = open.leaf {
+                self.append_line(lines, 4);
+                *len = lines.len();
+            }
+            open.leaf_end = open.line_end.unwrap_or(open.leaf_end);
+            return;
+        }
+
+        if open.leaf.is_paragraph() {
+            let m = self.cur.mark();
+            if let Success(level) = self.parse_setext_underline() {
+                if let LfParagraph(lines) = mem::replace(&mut open.leaf, LfNone) {
+                    if let Some(content) = self.paragraph_content(lines) {
+                        m.cancel();
+                        let (start, end) = (open.leaf_start, open.line_end.unwrap_or(open.leaf_end));
+                        self.add_block(open, Heading { level: level, content: content }, start, end);
+                        return;
+                    }
+                }
+                // only reference definitions, the underline is something else
+            }
+        }
+
+        if let Success((indent, fc, n)) = self.code_fence_start() {
+            self.close_leaf(open);
+            if after_blank { open.loosen(); }
+            let tag = self.read_code_info();
+            open.start_leaf(LfFencedCode {
+                indent: indent,
+                fc: fc,
+                n: n,
+                tag: tag,
+                lines: Lines::new(self.cur.phantom_mark())
+            }, start);
+            return;
+        }
+
+        let block = first_of! {
+            self.parse_atx_heading(),
+            self.parse_horizontal_rule()
+        };
+        if let Success(block) = block {
+            self.close_leaf(open);
+            if after_blank { open.loosen(); }
+            let end = open.line_end.unwrap_or(start);
+            self.add_block(open, block, start, end);
+            return;
+        }
+
+        if !open.leaf.is_paragraph() {
+            self.close_leaf(open);
+            if after_blank { open.loosen(); }
+            open.start_leaf(LfParagraph(Lines::new(self.cur.phantom_mark())), start);
+        }
+        self.append_to_leaf(open, 0);
+    }
+
+    fn close_blocks(&self, open: &mut OpenBlocks<'a>) {
+        self.close_containers(open, 1);
+        self.close_leaf(open);
+    }
+}
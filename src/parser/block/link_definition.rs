@@ -0,0 +1,149 @@
+use parser::{MarkdownParser, ParseResult, Success, End, NoParse};
+use tokens::*;
+use util::{ByteSliceOps, CharOps, decode_text};
+
+pub trait LinkDefinitionParser {
+    fn parse_link_definition(&self) -> ParseResult<()>;
+}
+
+// link definitions can't end the document by themselves: if there is no
+// complete definition, the line is parsed as something else
+macro_rules! parse_or_no_parse {
+    ($e:expr) => (
+        match $e {
+            Success(r) => r,
+            NoParse | End => return NoParse
+        }
+    )
+}
+
+impl<'a> LinkDefinitionParser for MarkdownParser<'a> {
+    fn parse_link_definition(&self) -> ParseResult<()> {
+        debug!(">> trying link definition");
+        if !self.cur.available() { return End }
+        if self.link_map.borrow().len() >= self.config.max_link_references {
+            return NoParse;
+        }
+
+        let start = self.cur.pos.get();
+        let m = self.cur.mark();
+        parse_or_no_parse!(self.try_skip_initial_spaces());
+        parse_or_no_parse!(self.try_read_char(b'['));
+
+        // read label until unescaped closing bracket
+        let pm = self.cur.phantom_mark();
+        let mut escaping = false;
+        loop {
+            match self.cur.next_byte() {
+                None => return NoParse,
+                Some(_) if escaping => escaping = false,
+                Some(b'\\') => escaping = true,
+                Some(b'[') => return NoParse,
+                Some(b']') => break,
+                Some(_) => {}
+            }
+        }
+        let label = self.cur.slice_until_now_from(pm);
+        if label.trim_left(|b: u8| b.is_space()).is_empty() { return NoParse }
+
+        parse_or_no_parse!(self.try_read_char(b':'));
+
+        // destination may start on the next line
+        self.skip_spaces();
+        if self.try_read_line_end().is_success() {
+            self.skip_spaces();
+        }
+
+        let link = match self.cur.current_byte() {
+            Some(b'<') => {
+                self.cur.next();
+                let pm = self.cur.phantom_mark();
+                loop {
+                    match self.cur.next_byte() {
+                        Some(b'>') => break,
+                        Some(b'\n') | Some(b'\r') | None => return NoParse,
+                        Some(_) => {}
+                    }
+                }
+                self.cur.slice_until_now_from(pm)
+            }
+            Some(_) => {
+                let pm = self.cur.phantom_mark();
+                while self.cur.current_byte().map(|b| !b.is_space()).unwrap_or(false) {
+                    self.cur.next();
+                }
+                self.cur.slice_to_now_from(pm)
+            }
+            None => return NoParse
+        };
+        if link.is_empty() { return NoParse }
+
+        // the title is optional and may start on the next line; if it is
+        // malformed, the definition ends after the destination
+        let title = {
+            let tm = self.cur.mark();
+            match self.parse_link_definition_title() {
+                Some(title) if self.try_skip_rest_of_line() => { tm.cancel(); Some(title) }
+                _ => { tm.reset(); None }
+            }
+        };
+        if title.is_none() && !self.try_skip_rest_of_line() {
+            return NoParse;
+        }
+
+        m.cancel();
+
+        self.add_link(LinkDescription {
+            id: String::from_utf8_lossy(label).into_owned(),
+            link: String::from_utf8_lossy(link).into_owned(),
+            title: title.map(|t| decode_text(t).into_owned())
+        }, start);
+
+        Success(())
+    }
+}
+
+trait Ops<'a> {
+    fn parse_link_definition_title(&self) -> Option<&'a [u8]>;
+    fn try_skip_rest_of_line(&self) -> bool;
+}
+
+impl<'a> Ops<'a> for MarkdownParser<'a> {
+    fn parse_link_definition_title(&self) -> Option<&'a [u8]> {
+        self.skip_spaces();
+        if self.try_read_line_end().is_success() {
+            self.skip_spaces();
+        }
+
+        let cc = match self.cur.next_byte() {
+            Some(b'"') => b'"',
+            Some(b'\'') => b'\'',
+            Some(b'(') => b')',
+            _ => return None
+        };
+
+        let pm = self.cur.phantom_mark();
+        let mut escaping = false;
+        loop {
+            match self.cur.next_byte() {
+                None => return None,
+                Some(_) if escaping => escaping = false,
+                Some(b'\\') => escaping = true,
+                Some(c) if c == cc => break,
+                Some(_) => {}
+            }
+        }
+
+        Some(self.cur.slice_until_now_from(pm))
+    }
+
+    fn try_skip_rest_of_line(&self) -> bool {
+        loop {
+            match self.cur.next_byte() {
+                Some(b' ') | Some(b'\t') => {}
+                Some(b'\n') | Some(b'\r') | None => { self.complete_line_end(); return true }
+                Some(_) => return false
+            }
+        }
+    }
+}
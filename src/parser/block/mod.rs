@@ -1,36 +1,56 @@
-use parser::{MarkdownParser, ParseResult, End};
+use parser::{MarkdownParser, Piece, ParseResult, Success, End};
 use tokens::*;
+use util::{ByteSliceOps, CharOps, decode_text};
 
-use self::block_quote::BlockQuoteParser;
-use self::block_code::BlockCodeParser;
-use self::atx_heading::AtxHeadingParser;
-use self::lists::ListsParser;
-use self::misc::MiscParser;
+use self::container::ContainerParser;
 
-mod block_quote;
+pub use self::container::OpenBlocks;
+
+mod container;
 mod block_code;
 mod atx_heading;
-mod lists;
 mod misc;
+mod link_definition;
+
+pub trait BlockParser<'a> {
+    fn parse_piece(&self) -> ParseResult<Piece<'a>>;
+}
+
+trait Ops<'a> {
+    fn overflow_paragraph(&self) -> ParseResult<Piece<'a>>;
+}
+
+impl<'a> Ops<'a> for MarkdownParser<'a> {
+    // the source past the size limit is a single paragraph of plain text
+    fn overflow_paragraph(&self) -> ParseResult<Piece<'a>> {
+        let rest = self.overflow.get();
+        self.overflow.set(&[]);
+        let rest = rest.trim_right(|b: u8| b.is_line_end());
+        if rest.is_empty() { return End }
 
-pub trait BlockParser {
-    fn parse_block(&self) -> ParseResult<Block>;
+        // the overflow directly follows the buffer
+        let start = self.cur.buf.len();
+        Success(Piece::Leaf(Paragraph(vec![Chunk(decode_text(rest))]), start, start + rest.len()))
+    }
 }
 
-impl<'a> BlockParser for MarkdownParser<'a> {
-    fn parse_block(&self) -> ParseResult<Block> {
+impl<'a> BlockParser<'a> for MarkdownParser<'a> {
+    // reads lines until a container is opened or closed or a leaf block is closed
+    fn parse_piece(&self) -> ParseResult<Piece<'a>> {
         debug!("--- parsing a block");
-        // Skip empty lines
-        while ret_on_end!(self.try_parse_empty_line()).is_success() {}
-
-        first_of! {
-            self.parse_block_quote(),
-            self.parse_block_code(),
-            self.parse_horizontal_rule(),
-            self.parse_atx_heading(),
-            self.parse_list(),
-            self.parse_paragraph(),
-            panic!("programming error, parsing block failed")
+        loop {
+            if let Some(piece) = self.event_queue.borrow_mut().pop_front() {
+                return Success(piece);
+            }
+
+            let mut open = self.open_blocks.borrow_mut();
+            if self.cur.available() {
+                self.parse_line(&mut *open);
+            } else if !open.is_empty() {
+                self.close_blocks(&mut *open);
+            } else {
+                return self.overflow_paragraph();
+            }
         }
     }
 }
@@ -1,28 +1,45 @@
+use std::usize;
+
 #[derive(Copy)]
 pub struct MarkdownConfig {
-    pub trim_newlines: bool
+    pub trim_newlines: bool,
+    /// Whether code blocks keep CRLF and CR line endings of the source
+    /// instead of having them replaced with LF.
+    pub keep_line_endings: bool,
+    /// How deep block quotes and lists can be nested, counting each block
+    /// quote and each list once, not their items; markers of deeper
+    /// containers are left as text.
+    pub max_nesting_depth: usize,
+    /// How deep emphasis can be nested; deeper emphasis characters are left
+    /// as text.
+    pub max_inline_nesting: usize,
+    /// How many bytes of the source are parsed; the rest of the document,
+    /// from the line the limit falls on, becomes a paragraph of plain text.
+    pub max_document_size: usize,
+    /// How many distinct reference definitions are recorded; further
+    /// definitions are left as paragraph text.
+    pub max_link_references: usize
 }
 
 impl MarkdownConfig {
     #[inline]
     pub fn default() -> MarkdownConfig {
         MarkdownConfig {
-            trim_newlines: true
+            trim_newlines: true,
+            keep_line_endings: false,
+            max_nesting_depth: 64,
+            max_inline_nesting: 32,
+            max_document_size: usize::MAX,
+            max_link_references: 65536
         }
     }
 }
 
-macro_rules! impl_setters {
-    ($target:ident; $($name:ident : $t:ty),+) => ($(
-        impl $target {
-            pub fn $name(mut self, value: $t) -> $target {
-                self.$name = value;
-                self
-            }
-        }
-    )+)
-}
-
 impl_setters! { MarkdownConfig;
-    trim_newlines: bool
+    trim_newlines: bool,
+    keep_line_endings: bool,
+    max_nesting_depth: usize,
+    max_inline_nesting: usize,
+    max_document_size: usize,
+    max_link_references: usize
 }
@@ -0,0 +1,191 @@
+use std::str;
+use std::collections::HashSet;
+
+use tokens::LinkMap;
+
+pub use self::DiagnosticKind::*;
+
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum DiagnosticKind {
+    /// A reference link or image whose label has no definition.
+    UnresolvedReference,
+    /// A reference definition which is not used by any link or image.
+    UnusedDefinition,
+    /// A reference definition shadowed by an earlier one with the same label.
+    DuplicateDefinition
+}
+
+/// A position in the source buffer. Lines and columns start from 1; columns
+/// are counted in characters.
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+/// A part of the source from `start` up to, but not including, `end`.
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// The label as it is written in the source.
+    pub label: String,
+    pub position: Position
+}
+
+// a reference label as seen by the parser, at an offset in the source buffer;
+// the position is known in advance if the buffer is not kept
+struct LabelSite {
+    key: String,
+    label: String,
+    offset: usize,
+    position: Option<Position>
+}
+
+/// Link references and definitions encountered during parsing.
+pub struct ReferenceLog {
+    references: Vec<LabelSite>,
+    definitions: Vec<LabelSite>
+}
+
+impl ReferenceLog {
+    pub fn new() -> ReferenceLog {
+        ReferenceLog {
+            references: Vec::new(),
+            definitions: Vec::new()
+        }
+    }
+
+    pub fn add_reference(&mut self, key: String, label: String, offset: usize) {
+        self.references.push(LabelSite { key: key, label: label, offset: offset, position: None });
+    }
+
+    pub fn add_definition(&mut self, key: String, label: String, offset: usize) {
+        self.definitions.push(LabelSite { key: key, label: label, offset: offset, position: None });
+    }
+
+    /// Computes positions of the sites logged so far which lie in `source`,
+    /// a piece of the document starting at `start`, so that the piece need
+    /// not be kept until diagnostics are requested.
+    pub fn locate(&mut self, source: &[u8], start: Position) {
+        let lines = LineIndex::new(source);
+        let end = start.offset + source.len();
+        let sites = self.references.iter_mut().chain(self.definitions.iter_mut());
+        for site in sites.filter(|s| s.position.is_none() && s.offset >= start.offset && s.offset <= end) {
+            site.position = Some(lines.position_from(start, site.offset));
+        }
+    }
+
+    /// Checks logged references against the link map. `source` is the buffer
+    /// the offsets refer to; diagnostics are sorted by their position.
+    pub fn diagnostics(&self, link_map: &LinkMap, source: &[u8]) -> Vec<Diagnostic> {
+        let mut result = Vec::new();
+        let lines = LineIndex::new(source);
+
+        let mut used = HashSet::new();
+        for r in self.references.iter() {
+            if link_map.contains_key(&r.key) {
+                used.insert(r.key.as_slice());
+            } else {
+                result.push(lines.diagnostic(UnresolvedReference, r));
+            }
+        }
+
+        let mut defined = HashSet::new();
+        let mut definitions: Vec<&LabelSite> = self.definitions.iter().collect();
+        definitions.sort_by(|a, b| a.offset.cmp(&b.offset));
+        for d in definitions.into_iter() {
+            if !defined.insert(d.key.as_slice()) {
+                result.push(lines.diagnostic(DuplicateDefinition, d));
+            } else if !used.contains(d.key.as_slice()) {
+                result.push(lines.diagnostic(UnusedDefinition, d));
+            }
+        }
+
+        result.sort_by(|a, b| a.position.offset.cmp(&b.position.offset));
+        result
+    }
+}
+
+struct LineIndex<'a> {
+    source: &'a [u8],
+    line_starts: Vec<usize>
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(source: &'a [u8]) -> LineIndex<'a> {
+        let mut line_starts = vec![0];
+        for (i, &b) in source.iter().enumerate() {
+            // CRLF is a single line ending
+            if b == b'\n' || (b == b'\r' && source.get(i + 1) != Some(&b'\n')) {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex {
+            source: source,
+            line_starts: line_starts
+        }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1  // i > 0 because line_starts[0] == 0
+        };
+        let start = self.line_starts[line];
+        let end = if offset > self.source.len() { self.source.len() } else { offset };
+        let prefix = &self.source[start..end];
+        let column = match str::from_utf8(prefix) {
+            Ok(s) => s.chars().count(),
+            Err(_) => prefix.len()
+        };
+        Position {
+            offset: offset,
+            line: line + 1,
+            column: column + 1
+        }
+    }
+
+    // the position of an offset in the document, the source of which is a
+    // piece of the document starting at start
+    fn position_from(&self, start: Position, offset: usize) -> Position {
+        let p = self.position(offset - start.offset);
+        Position {
+            offset: offset,
+            line: start.line + p.line - 1,
+            column: if p.line == 1 { start.column + p.column - 1 } else { p.column }
+        }
+    }
+
+    fn diagnostic(&self, kind: DiagnosticKind, site: &LabelSite) -> Diagnostic {
+        Diagnostic {
+            kind: kind,
+            label: site.label.clone(),
+            position: match site.position {
+                Some(position) => position,
+                None => self.position(site.offset)
+            }
+        }
+    }
+}
+
+/// Returns the position right after `source`, a piece of the document
+/// starting at `start`.
+pub fn position_after(source: &[u8], start: Position) -> Position {
+    LineIndex::new(source).position_from(start, start.offset + source.len())
+}
+
+/// Returns spans of pairs of offsets in the source.
+pub fn spans(source: &[u8], offsets: &[(usize, usize)]) -> Vec<Span> {
+    let lines = LineIndex::new(source);
+    offsets.iter().map(|&(start, end)| Span {
+        start: lines.position(start),
+        end: lines.position(end)
+    }).collect()
+}
@@ -1,41 +1,37 @@
-use std::str;
-use std::borrow::ToOwned;
-
 use parser::{MarkdownParser, PhantomMark, Success, End, NoParse};
 use tokens::*;
-use util::CharOps;
+use util::{CharOps, decode_text};
 
 use super::InlineParser;
 
-pub trait EmphasisParser {
-    fn parse_emphasis(&self, ec: u8, n: usize) -> Option<Inline>;
+pub trait EmphasisParser<'a> {
+    fn parse_emphasis(&self, ec: u8, n: usize) -> Option<Inline<'a>>;
 }
 
-impl<'a> EmphasisParser for MarkdownParser<'a> {
-    fn parse_emphasis(&self, ec: u8, n: usize) -> Option<Inline> {
+impl<'a> EmphasisParser<'a> for MarkdownParser<'a> {
+    fn parse_emphasis(&self, ec: u8, n: usize) -> Option<Inline<'a>> {
         debug!("reading emphasis, char [{}], n = {}", ec as char, n);
+        if !ec.is_code() && self.inline_depth >= self.config.max_inline_nesting {
+            return None;
+        }
         let pm = self.cur.phantom_mark();
-        loop {
-            // a marker over the first character of closing emphasis
-            let pm_last = opt_ret!(self.until_emph_closing(ec, n));
-            let slice = self.cur.slice(pm, pm_last);
-            debug!("checking slice: [{}], n: {}", ::std::str::from_utf8(slice).unwrap(), n);
-
-            // escaped closing emphasis
-            if slice[slice.len()-1] != b' ' {
-                if ec.is_code() {  // this is code inline
-                    return Some(Code(str::from_utf8(slice).unwrap().to_owned()));
-                } else {
-                    let subp = self.fork(slice);
-                    let result = self.fix_links(subp.parse_inline());
-                    
-                    return Some(match n {
-                        1 => Emphasis(result),
-                        2 => MoreEmphasis(result),
-                        _ => unreachable!()  // for now
-                    });
-                }
-            }
+        // a marker over the first character of closing emphasis
+        let pm_last = opt_ret!(self.until_emph_closing(ec, n));
+        let slice = self.cur.slice(pm, pm_last);
+        debug!("checking slice: [{}], n: {}", String::from_utf8_lossy(slice), n);
+
+        if ec.is_code() {  // this is code inline
+            Some(Code(decode_text(slice)))
+        } else {
+            let mut subp = self.fork(pm.pos, pm_last.pos);
+            subp.inline_depth = self.inline_depth + 1;
+            let result = subp.parse_inline();
+
+            Some(match n {
+                1 => Emphasis(result),
+                2 => MoreEmphasis(result),
+                _ => unreachable!()  // for now
+            })
         }
     }
 }
@@ -52,6 +48,10 @@ impl<'a> Ops<'a> for MarkdownParser<'a> {
         let mut pm_last = pm;
         let mut escaping = false;
 
+        if self.is_unclosed((ec, n), pm.pos) { return None }
+        // bytes from here on are not inside skipped code or links
+        let mut plain_from = pm.pos;
+
         macro_rules! advance {
             () => (pm_last = self.cur.phantom_mark())
         }
@@ -62,7 +62,12 @@ impl<'a> Ops<'a> for MarkdownParser<'a> {
         'outer: loop {
             let c = match self.cur.next_byte() {
                 Some(c) => c,
-                None => return None  // if we're here then we haven't found the closing "brace"
+                None => {
+                    // if we're here then we haven't found the closing "brace", and
+                    // neither would a scan starting anywhere after plain_from
+                    self.set_unclosed((ec, n), plain_from);
+                    return None
+                }
             };
             
             match c {
@@ -87,7 +92,9 @@ impl<'a> Ops<'a> for MarkdownParser<'a> {
                         }
                     }
 
-                    if rn == n {  // this is our emphasis boundary, finish reading
+                    // a boundary after a space is escaped closing emphasis
+                    let escaped = pm_last != pm && self.cur.buf[pm_last.pos - 1] == b' ';
+                    if rn == n && !escaped {  // this is our emphasis boundary, finish reading
                         m.cancel();
                         break;
                     } else {  
@@ -127,6 +134,7 @@ impl<'a> Ops<'a> for MarkdownParser<'a> {
 
                     retract!();
                     ec_mark.map(|m| m.cancel());
+                    plain_from = self.cur.pos.get();
                 }
 
                 // skip hyperlinks
@@ -151,13 +159,18 @@ impl<'a> Ops<'a> for MarkdownParser<'a> {
 
                     // skip whitespace between delimiting braces
                     parse_or_break!(self.skip_spaces_and_newlines());
-                    debug!("skipped whitespace, current char: {}", *self.cur as char);
+                    debug!("skipped whitespace, current char: {:?}", self.cur.current_byte());
 
                     // determine closing brace for the second part of the link
-                    let cc = match *self.cur {
-                        b'[' => b']',
-                        b'(' => b')',
-                        _ => if ec_mark.is_some() { break 'outer } else { continue 'outer }
+                    let cc = match self.cur.current_byte() {
+                        Some(b'[') => b']',
+                        Some(b'(') => b')',
+                        _ => if ec_mark.is_some() {
+                            break 'outer
+                        } else {
+                            plain_from = self.cur.pos.get();
+                            continue 'outer
+                        }
                     };
                     self.cur.next();
 
@@ -179,6 +192,7 @@ impl<'a> Ops<'a> for MarkdownParser<'a> {
 
                     retract!();
                     ec_mark.map(|m| m.cancel());
+                    plain_from = self.cur.pos.get();
                 }
 
                 // just pass through any other character
@@ -1,17 +1,19 @@
 use parser::{MarkdownParser, ParseResult, Success, End};
 use tokens::*;
 
-pub trait EscapeParser {
-    fn parse_escape(&self) -> ParseResult<Option<Inline>>;
+pub trait EscapeParser<'a> {
+    fn parse_escape(&self) -> ParseResult<Option<Inline<'a>>>;
 }
 
-impl<'a> EscapeParser for MarkdownParser<'a> {
-    fn parse_escape(&self) -> ParseResult<Option<Inline>> {
-        const ESCAPE_CHARS: &'static [u8] = b"\\`*_{}[]()#+-.!:|&<>^~";
+impl<'a> EscapeParser<'a> for MarkdownParser<'a> {
+    fn parse_escape(&self) -> ParseResult<Option<Inline<'a>>> {
+        const ESCAPE_CHARS: &'static [u8] = b"\\`*_{}[]()#+-.!:|&<>^~=";
 
         match self.cur.next_byte() {
-            Some(c) if ESCAPE_CHARS.contains(&c) => 
-                Success(Some(Chunk(String::from_utf8(vec![c]).unwrap()))),
+            Some(c) if ESCAPE_CHARS.contains(&c) => {
+                let pm = self.cur.phantom_mark_at_prev();
+                Success(Some(Chunk(String::from_utf8_lossy(self.cur.slice_to_now_from(pm)))))
+            }
             Some(_) => Success(None),
             None => End
         }
@@ -1,24 +1,32 @@
-use std::str;
-use std::borrow::ToOwned;
 
 use parser::{MarkdownParser, Success, End, NoParse};
 use tokens::*;
 use util::{ByteSliceOps, CharOps};
 
-pub trait LinkParser {
-    fn parse_link(&self, is_image: bool) -> Option<Inline>;
+pub trait LinkParser<'a> {
+    fn parse_link(&self, is_image: bool) -> Option<Inline<'a>>;
 }
 
-impl<'a> LinkParser for MarkdownParser<'a> {
-    fn parse_link(&self, is_image: bool) -> Option<Inline> {
+impl<'a> LinkParser<'a> for MarkdownParser<'a> {
+    fn parse_link(&self, is_image: bool) -> Option<Inline<'a>> {
         let pm = self.cur.phantom_mark();
         let label;
 
+        if self.is_unclosed((b'[', 1), pm.pos) { return None }
+
         // find matching closing brace
         let mut escaping = false;
         let mut level = 1usize;
+        // past the last closing brace, no later label can be closed either
+        let mut after_last_close = pm.pos;
         loop {
-            let c = opt_ret!(self.cur.next_byte());
+            let c = match self.cur.next_byte() {
+                Some(c) => c,
+                None => {
+                    self.set_unclosed((b'[', 1), after_last_close);
+                    return None
+                }
+            };
             match c {
                 b'\\' => escaping = true,
                 _ if escaping => escaping = false,
@@ -26,6 +34,7 @@ impl<'a> LinkParser for MarkdownParser<'a> {
                 b']' => {
                     level -= 1;
                     if level <= 0 { break; }
+                    after_last_close = self.cur.pos.get();
                 }
                 _ => {}
             }
@@ -63,32 +72,34 @@ impl<'a> LinkParser for MarkdownParser<'a> {
                         b')' => if level == 0 { break; } else { level -= 1; },
                         // encountered link title
                         cc if (cc == b'\'' || cc == b'"') &&
-                            self.cur.peek_before_prev().is_space() => break,
+                            self.cur.peek_before_prev().map(|b| b.is_space()).unwrap_or(false) => break,
                         _ => {}  // just pass through
                     }
                 }
 
                 let link_slice = self.cur.slice_until_now_from(pm);
-                debug!("read link slice: {}", str::from_utf8(link_slice).unwrap());
+                debug!("read link slice: {}", String::from_utf8_lossy(link_slice));
 
                 // read title, if it is there
-                let pc = self.cur.peek_prev();
-                if pc == b'\'' || pc == b'\"' {  // title
-                    let pm = self.cur.phantom_mark();
-
-                    let mut read_title = false;
-                    loop {
-                        let c = opt_ret!(self.cur.next_byte());
-                        match c {
-                            b'\\' => { self.cur.next(); },  // skip escaped byte
-                            cc if cc == pc && !read_title => {
-                                title = Some(self.cur.slice_until_now_from(pm));
-                                read_title = true;
+                match self.cur.peek_prev() {
+                    Some(pc) if pc == b'\'' || pc == b'"' => {  // title
+                        let pm = self.cur.phantom_mark();
+
+                        let mut read_title = false;
+                        loop {
+                            let c = opt_ret!(self.cur.next_byte());
+                            match c {
+                                b'\\' => { self.cur.next(); },  // skip escaped byte
+                                cc if cc == pc && !read_title => {
+                                    title = Some(self.cur.slice_until_now_from(pm));
+                                    read_title = true;
+                                }
+                                b')' if read_title => break,
+                                _ => {}
                             }
-                            b')' if read_title => break,
-                            _ => {}
                         }
                     }
+                    _ => {}
                 }
                 
                 link = Some(
@@ -124,11 +135,24 @@ impl<'a> LinkParser for MarkdownParser<'a> {
         }
 
         // TODO: parse link contents
-        let text = vec![Chunk(str::from_utf8(label).unwrap().to_owned())];
-
-        let link = link.map(|link| str::from_utf8(link).unwrap().to_owned());
-        let id = id.map(|id| str::from_utf8(id).unwrap().to_owned());
-        let title = title.map(|title| str::from_utf8(title).unwrap().to_owned());
+        let text = vec![Chunk(String::from_utf8_lossy(label))];
+
+        let link = link.map(|link| String::from_utf8_lossy(link));
+        let id = id.map(|id| String::from_utf8_lossy(id));
+        let title = title.map(|title| String::from_utf8_lossy(title));
+
+        // remember the reference for diagnostics; collapsed references use their text as a label
+        match id {
+            Some(ref id) => {
+                let start = pm.pos.saturating_sub(if is_image { 2 } else { 1 });
+                if id.trim().is_empty() {
+                    self.add_reference(&*String::from_utf8_lossy(label), start);
+                } else {
+                    self.add_reference(&**id, start);
+                }
+            }
+            None => {}
+        }
 
         let link = if is_image {
             Image {
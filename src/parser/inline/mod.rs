@@ -1,6 +1,8 @@
-use parser::{MarkdownParser, MarkdownConfig, Cursor, PhantomMark, End};
+use std::borrow::Cow;
+
+use parser::{MarkdownParser, MarkdownConfig, Cursor, PhantomMark, Success, End};
 use tokens::*;
-use util::CharOps;
+use util::{CharOps, decode_text};
 
 use self::emphasis::EmphasisParser;
 use self::escape::EscapeParser;
@@ -10,12 +12,27 @@ mod emphasis;
 mod escape;
 mod link;
 
-pub trait InlineParser {
-    fn parse_inline(&self) -> Text;
+pub trait InlineParser<'a> {
+    fn parse_inline(&self) -> Text<'a>;
+}
+
+// A bit set of bytes which may start an inline element: `\`, `*`, `_`, `` ` ``
+// and `[`. Runs of other bytes are skipped at once. A byte handled by
+// parse_inline must be added here.
+static SPECIAL: [u8; 32] = [
+    0, 0, 0, 0, 0, 0x04, 0, 0,                   // *
+    0, 0, 0, 0x08 | 0x10 | 0x80, 0x01, 0, 0, 0,  // [ \ _ `
+    0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0
+];
+
+#[inline]
+fn is_special(b: u8) -> bool {
+    SPECIAL[(b >> 3) as usize] & (1 << (b & 7)) != 0
 }
 
 struct InlineParsingState<'b, 'a: 'b> {
-    tokens: Vec<Inline>,
+    tokens: Vec<Inline<'a>>,
     cur: &'b Cursor<'a>,
     config: &'b MarkdownConfig,
     pm: PhantomMark,
@@ -29,7 +46,7 @@ impl<'b, 'a> InlineParsingState<'b, 'a> {
         self.pm_last = self.pm;
     }
 
-    fn push_token(&mut self, mut token: Inline) {
+    fn push_token(&mut self, mut token: Inline<'a>) {
         fn is_chunk(token: Option<&Inline>) -> bool {
             match token {
                 Some(&Chunk(_)) => true,
@@ -39,10 +56,14 @@ impl<'b, 'a> InlineParsingState<'b, 'a> {
 
         match token {
             // remove trailing newlines from chunks
-            Chunk(ref mut buf) if self.config.trim_newlines =>
-                while buf.len() > 0 && buf.as_slice().chars().rev().next().unwrap() == '\n' {
-                    buf.pop();
-                },
+            Chunk(ref mut buf) if self.config.trim_newlines => {
+                let new_len = buf.trim_right_matches(|c: char| c == '\n' || c == '\r').len();
+                let trimmed = match *buf {
+                    Cow::Borrowed(s) => Some(&s[..new_len]),
+                    Cow::Owned(ref mut s) => { s.truncate(new_len); None }
+                };
+                if let Some(s) = trimmed { *buf = Cow::Borrowed(s); }
+            }
             _ => {}
         }
 
@@ -55,7 +76,7 @@ impl<'b, 'a> InlineParsingState<'b, 'a> {
         match token {
             Chunk(buf0) => if is_chunk(self.tokens.last()) {
                 match self.tokens.last_mut().unwrap() {
-                    &mut Chunk(ref mut buf) => buf.push_str(buf0.as_slice()),
+                    &mut Chunk(ref mut buf) => buf.to_mut().push_str(&*buf0),
                     _ => unreachable!()
                 }
             } else {
@@ -69,12 +90,10 @@ impl<'b, 'a> InlineParsingState<'b, 'a> {
         {
             debug!(">> pushing chunk from {} to {}", self.pm.pos, self.pm_last.pos);
             let slice = self.cur.slice(self.pm, self.pm_last);
-            debug!(">> chunk: {}", ::std::str::from_utf8(slice).unwrap());
+            debug!(">> chunk: {}", String::from_utf8_lossy(slice));
             if slice.is_empty() { return; }
 
-            let chunk = slice.to_vec();
-            // TODO: handle UTF-8 decoding error
-            self.push_token(Chunk(String::from_utf8(chunk).unwrap()));
+            self.push_token(Chunk(decode_text(slice)));
         }
 
         self.update();
@@ -88,8 +107,8 @@ impl<'b, 'a> InlineParsingState<'b, 'a> {
 }
 
 
-impl<'a> InlineParser for MarkdownParser<'a> {
-    fn parse_inline(&self) -> Text {
+impl<'a> InlineParser<'a> for MarkdownParser<'a> {
+    fn parse_inline(&self) -> Text<'a> {
         debug!(">> parsing inline");
 
         let mut s = InlineParsingState {
@@ -102,15 +121,26 @@ impl<'a> InlineParser for MarkdownParser<'a> {
 
         loop {
             debug!(">> cursor positon: {}", self.cur.pos.get());
+
+            // plain text goes to the current chunk as a whole
+            let n = if !self.skip_plain_text { 0 } else {
+                let rest = &self.cur.buf[self.cur.pos.get()..];
+                rest.iter().position(|&b| is_special(b)).unwrap_or(rest.len())
+            };
+            if n > 0 {
+                self.cur.advance(n);
+                s.advance();
+            }
+
             let c = opt_break!(self.cur.next_byte());
             match c {
-                b'\\' => match break_on_end!(self.parse_escape()).unwrap() {
-                    Some(token) => {
+                b'\\' => match break_on_end!(self.parse_escape()) {
+                    Success(Some(token)) => {
                         s.push_chunk();
                         s.push_token(token);
                         s.update();
                     }
-                    None => s.advance()
+                    _ => s.advance()
                 },
 
                 c if c.is_emphasis() || c.is_code() => {
@@ -142,7 +172,9 @@ impl<'a> InlineParser for MarkdownParser<'a> {
                 b'[' => {
                     debug!(">> encountered link start");
 
-                    let is_image = self.cur.peek_before_prev_opt() == Some(b'!');
+                    // a `!` consumed by an escape is before the phantom mark
+                    let is_image = self.cur.pos.get() >= s.pm.pos + 2 &&
+                                   self.cur.peek_before_prev() == Some(b'!');
                     if is_image { self.cur.retract(2); s.advance(); }
                     s.push_chunk();
                     if is_image { self.cur.advance(2); s.update(); }
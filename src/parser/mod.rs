@@ -1,15 +1,24 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::cell::{RefCell, Cell};
+use std::cell::{RefCell, Ref, Cell};
+use std::rc::Rc;
 use std::ops::Deref;
+use std::mem;
+use std::cmp::{self, Ordering};
+use std::iter;
 
 pub use self::config::*;
+pub use self::diagnostics::{Diagnostic, DiagnosticKind, Position, Span};
+pub use self::diagnostics::DiagnosticKind::*;
+pub use self::stream::StreamingParser;
 use tokens::*;
+use events::{Event, Tag, BlockBuilder, push_block_events};
 
 pub use self::ParseResult::*;
-use self::block::BlockParser;
+use self::block::{BlockParser, OpenBlocks};
+use self::diagnostics::ReferenceLog;
 
-use util::{CellOps, ByteMatcher};
+use util::{CellOps, ByteMatcher, ByteSliceOps, decode_text};
 
 macro_rules! first_of {
     ($e:expr) => ($e);
@@ -104,15 +113,35 @@ macro_rules! cancel {
 
 pub mod config;
 
+mod diagnostics;
+mod stream;
+
 mod block;
 mod inline;
 
+static BOM: &'static [u8] = b"\xef\xbb\xbf";
+
+// tabs advance to the next multiple of this column
+const TAB_STOP: usize = 4;
+
+#[inline]
+fn tab_width(column: usize) -> usize {
+    TAB_STOP - column % TAB_STOP
+}
+
 // Cursor employs inner mutability to support RAII marks.
 // Parser employs inner mutability as a consequence of this.
 
+// Besides the position the cursor tracks how many columns of the tab under it
+// are already consumed, so a container prefix can end in the middle of a tab.
+// Any movement resets this.
 struct Cursor<'a> {
     buf: &'a [u8],
-    pos: Cell<usize>
+    pos: Cell<usize>,
+    partial: Cell<usize>,
+    // the last computed column and its position, columns of later
+    // positions are computed from it
+    last_column: Cell<(usize, usize)>
 }
 
 impl<'a> Deref for Cursor<'a> {
@@ -128,7 +157,9 @@ impl<'a> Cursor<'a> {
     fn new(buf: &[u8]) -> Cursor {
         Cursor {
             buf: buf,
-            pos: Cell::new(0)
+            pos: Cell::new(0),
+            partial: Cell::new(0),
+            last_column: Cell::new((0, 0))
         }
     }
 
@@ -137,10 +168,72 @@ impl<'a> Cursor<'a> {
     
     // TODO: rename to unsafe_advance? it does not check for buffer end
     #[inline]
-    fn advance(&self, n: usize) { self.pos.modify(|p| p + n); }
+    fn advance(&self, n: usize) { self.pos.modify(|p| p + n); self.partial.set(0); }
 
     #[inline]
-    fn retract(&self, n: usize) { self.pos.modify(|p| if n > p { 0 } else { p - n }); }
+    fn retract(&self, n: usize) {
+        self.pos.modify(|p| if n > p { 0 } else { p - n });
+        self.partial.set(0);
+    }
+
+    // column of the current position in its line, with tabs expanded
+    fn column(&self) -> usize {
+        let pos = self.pos.get();
+        let (start, column) = match self.last_column.get() {
+            (p, c) if p <= pos => (p, c),
+            _ => {
+                let start = self.buf[..pos].iter().rposition(|&b| b == b'\n' || b == b'\r')
+                    .map(|i| i + 1).unwrap_or(0);
+                (start, 0)
+            }
+        };
+        let column = self.buf[start..pos].iter().fold(column, |col, &b| match b {
+            b'\n' | b'\r' => 0,
+            b'\t' => col + tab_width(col),
+            b if b & 0xc0 == 0x80 => col,  // UTF-8 continuation byte
+            _ => col + 1
+        });
+        self.last_column.set((pos, column));
+        column + self.partial.get()
+    }
+
+    // number of columns of spaces and tabs starting at the current position
+    fn indent(&self) -> usize {
+        let start = self.column();
+        let mut col = start;
+        for &b in self.buf[self.pos.get()..].iter() {
+            match b {
+                b' ' => col += 1,
+                b'\t' => col += tab_width(col),
+                _ => break
+            }
+        }
+        col - start
+    }
+
+    // skips at most max columns of spaces and tabs, consuming a tab partially
+    // if it is wider than the remaining columns; returns the number of
+    // skipped columns
+    fn skip_indent(&self, max: usize) -> usize {
+        let mut n = 0;
+        while n < max && self.available() {
+            match **self {
+                b' ' => { self.advance(1); n += 1; }
+                b'\t' => {
+                    let width = tab_width(self.column());
+                    if n + width <= max {
+                        self.advance(1);
+                        n += width;
+                    } else {
+                        self.partial.modify(|p| p + max - n);
+                        n = max;
+                    }
+                }
+                _ => break
+            }
+        }
+        n
+    }
 
     #[inline]
     fn next(&self) -> bool {
@@ -162,17 +255,13 @@ impl<'a> Cursor<'a> {
     }
 
     #[inline]
-    fn peek_prev(&self) -> u8 {
-        self.buf[self.pos.get()-1]
-    }
-
-    #[inline]
-    fn peek_before_prev(&self) -> u8 {
-        self.buf[self.pos.get()-2]
+    fn peek_prev(&self) -> Option<u8> {
+        let pos = self.pos.get();
+        if pos > 0 { Some(self.buf[pos-1]) } else { None }
     }
 
     #[inline]
-    fn peek_before_prev_opt(&self) -> Option<u8> {
+    fn peek_before_prev(&self) -> Option<u8> {
         let pos = self.pos.get();
         if pos > 1 { Some(self.buf[pos-2]) } else { None }
     }
@@ -188,9 +277,6 @@ impl<'a> Cursor<'a> {
         }
     }
 
-    #[inline]
-    fn prev_byte(&self) -> u8 { self.retract(1); **self }
-
     #[inline]
     fn phantom_mark(&self) -> PhantomMark {
         PhantomMark { pos: self.pos.get() }
@@ -198,7 +284,7 @@ impl<'a> Cursor<'a> {
 
     #[inline]
     fn phantom_mark_at_prev(&self) -> PhantomMark {
-        PhantomMark { pos: self.pos.get()-1 }
+        PhantomMark { pos: self.pos.get().saturating_sub(1) }
     }
 
     #[inline]
@@ -208,7 +294,7 @@ impl<'a> Cursor<'a> {
 
     #[inline]
     fn mark(&self) -> Mark { 
-        Mark { cur: self, pos: self.pos.get(), cancelled: false }
+        Mark { cur: self, pos: self.pos.get(), partial: self.partial.get(), cancelled: false }
     }
 
     #[inline]
@@ -221,9 +307,11 @@ impl<'a> Cursor<'a> {
         &self.buf[pm.pos..self.pos.get()]
     }
 
+    // the slice up to the previous byte, empty if it is before the mark
     #[inline]
     fn slice_until_now_from(&self, pm: PhantomMark) -> &'a [u8] {
-        &self.buf[pm.pos..self.pos.get()-1]
+        let end = self.pos.get().saturating_sub(1);
+        if end < pm.pos { &[] } else { &self.buf[pm.pos..end] }
     }
 }
 
@@ -235,6 +323,7 @@ struct PhantomMark {
 struct Mark<'b, 'a: 'b> {
     cur: &'b Cursor<'a>,
     pos: usize,
+    partial: usize,
     cancelled: bool
 }
 
@@ -243,6 +332,7 @@ impl<'b, 'a> Drop for Mark<'b, 'a> {
     fn drop(&mut self) {
         if !self.cancelled {
             self.cur.pos.set(self.pos);
+            self.cur.partial.set(self.partial);
         }
     }
 }
@@ -255,54 +345,315 @@ impl<'b, 'a> Mark<'b, 'a> {
     fn reset(self) {}  // just invoke the destructor
 }
 
+// What the block parser produces: boundaries of containers as they are opened
+// and closed, and leaf blocks as a whole once they are closed, with offsets of
+// their start and end in the buffer.
+enum Piece<'a> {
+    Start(Tag<'a>, usize),
+    End(Tag<'a>, usize),
+    Leaf(Block<'a>, usize, usize)
+}
+
 pub struct MarkdownParser<'a> {
     cur: Cursor<'a>,
-    event_queue: RefCell<VecDeque<Block>>,
+    event_queue: RefCell<VecDeque<Piece<'a>>>,
+    // assembles containers for the block iterator
+    builder: BlockBuilder<'a>,
+    open_blocks: RefCell<OpenBlocks<'a>>,
     config: MarkdownConfig,
-    link_map: Option<LinkMap>
+    // how many emphasis spans enclose the buffer
+    inline_depth: usize,
+    // whether runs of plain text are skipped at once, see read_bytewise()
+    skip_plain_text: bool,
+    // for a delimiter and its length, the offset from which the delimiter
+    // is known to have no closing one up to the end of the buffer
+    unclosed: RefCell<HashMap<(u8, usize), usize>>,
+    // the part of the source past the size limit, it is not parsed
+    overflow: Cell<&'a [u8]>,
+    // the whole buffer, including the overflow
+    source: &'a [u8],
+
+    // offsets of blocks in the order they start, if they are recorded, and
+    // indices of those of them which are not closed yet
+    spans: Option<Vec<(usize, usize)>>,
+    open_spans: Vec<usize>,
+
+    // shared between the parser and its forks
+    link_map: Rc<RefCell<LinkMap>>,
+    reference_log: Rc<RefCell<ReferenceLog>>,
+
+    // maps buffer offsets to offsets in the original source: each entry is
+    // a pair of a buffer offset and a source offset of the same byte, with
+    // the bytes after it following contiguously; forks share the entries of
+    // their parent and keep the offset of their buffer in its buffer
+    origin: Rc<Vec<(usize, usize)>>,
+    origin_base: usize
 }
 
 // public methods
 impl<'a> MarkdownParser<'a> {
+    /// Creates a parser for the buffer. A leading UTF-8 byte order mark is
+    /// skipped.
     #[inline]
     pub fn new(buffer: &[u8]) -> MarkdownParser {
+        let cur = Cursor::new(buffer);
+        if buffer.starts_with(BOM) {
+            cur.advance(BOM.len());
+        }
         MarkdownParser {
-            cur: Cursor::new(buffer),
+            cur: cur,
             event_queue: RefCell::new(VecDeque::new()),
+            builder: BlockBuilder::new(),
+            open_blocks: RefCell::new(OpenBlocks::new()),
             config: MarkdownConfig::default(),
-            link_map: Some(HashMap::new())
+            inline_depth: 0,
+            skip_plain_text: true,
+            unclosed: RefCell::new(HashMap::new()),
+            overflow: Cell::new(&[]),
+            source: buffer,
+            spans: None,
+            open_spans: Vec::new(),
+            link_map: Rc::new(RefCell::new(HashMap::new())),
+            reference_log: Rc::new(RefCell::new(ReferenceLog::new())),
+            origin: Rc::new(vec![(0, 0)]),
+            origin_base: 0
         }
     }
 
-    #[inline]
+    /// Makes the parser read inline content byte by byte, as it did before
+    /// runs of plain text were skipped at once, to compare the speed of both.
+    #[cfg(feature = "bench")]
+    #[doc(hidden)]
+    pub fn read_bytewise(mut self) -> MarkdownParser<'a> {
+        self.skip_plain_text = false;
+        self
+    }
+
+    /// Sets the configuration; it should be done before parsing begins.
     pub fn with_config(mut self, config: MarkdownConfig) -> MarkdownParser<'a> {
         self.config = config;
+
+        // the source is cut after the last line which fits into the limit
+        let buf = self.cur.buf;
+        if buf.len() > config.max_document_size {
+            let end = buf[..config.max_document_size].iter().rposition(|&b| b == b'\n' || b == b'\r')
+                .map(|i| i + 1).unwrap_or(0);
+            let end = if end == 0 || buf[end-1] != b'\r' || buf.get(end) != Some(&b'\n') { end } else { end + 1 };
+            let pos = self.cur.pos.get();
+            self.cur = Cursor::new(&buf[..end]);
+            self.cur.pos.set(if pos > end { end } else { pos });
+            self.overflow.set(&buf[end..]);
+        }
         self
     }
 
+    /// Parses the whole document and resolves references in it.
+    ///
+    /// Blocks returned by the iterator interface are not resolved because
+    /// reference definitions may follow them; use `link_map()` to resolve them
+    /// after the iteration is finished.
+    pub fn read_all(mut self) -> Document<'a> {
+        let mut result: Document<'a> = self.by_ref().collect();
+        result.fix_links(&*self.link_map.borrow());
+        result
+    }
+
+    /// Like `read_all()`, but also keeps the reference definitions.
+    pub fn read_document(mut self) -> ParsedDocument<'a> {
+        let blocks = self.by_ref().collect();
+        let link_map = mem::replace(&mut *self.link_map.borrow_mut(), HashMap::new());
+        let mut result = ParsedDocument { blocks: blocks, link_map: link_map };
+        result.blocks.fix_links(&result.link_map);
+        result
+    }
+
+    /// Returns reference definitions encountered so far, keyed by normalized label.
+    #[inline]
+    pub fn link_map(&self) -> Ref<LinkMap> {
+        self.link_map.borrow()
+    }
+
+    /// Like `read_all()`, but also returns diagnostics about link references:
+    /// references without definitions and unused or duplicate definitions.
+    pub fn read_all_with_diagnostics(mut self) -> (Document<'a>, Vec<Diagnostic>) {
+        let mut result: Document<'a> = self.by_ref().collect();
+        result.fix_links(&*self.link_map.borrow());
+        let diagnostics = self.diagnostics();
+        (result, diagnostics)
+    }
+
+    /// Like `read_all()`, but also returns source spans of the blocks and list
+    /// items of the document, in the order they start: the span of a block
+    /// comes before the spans of blocks in it.
+    pub fn read_all_with_spans(mut self) -> (Document<'a>, Vec<Span>) {
+        self.spans = Some(Vec::new());
+        let mut result: Document<'a> = self.by_ref().collect();
+        result.fix_links(&*self.link_map.borrow());
+        let offsets = self.spans.take().unwrap_or(Vec::new());
+        (result, diagnostics::spans(self.source, offsets.as_slice()))
+    }
+
+    /// Returns diagnostics about link references in the part of the document
+    /// parsed so far.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.reference_log.borrow().diagnostics(&*self.link_map.borrow(), self.cur.buf)
+    }
+
+    /// Returns an iterator of events. Events of containers are returned as
+    /// soon as the containers are opened and closed, so memory use does not
+    /// grow with their size.
     #[inline]
-    pub fn read_all(self) -> Document {
-        self.collect()
+    pub fn events(self) -> MarkdownEvents<'a> {
+        MarkdownEvents {
+            parser: self,
+            queue: VecDeque::new()
+        }
     }
 }
 
 impl<'a> Iterator for MarkdownParser<'a> {
-    type Item = Block;
+    type Item = Block<'a>;
 
-    fn next(&mut self) -> Option<Block> { 
-        let front = self.event_queue.borrow_mut().pop_front();
-        front.or_else(|| self.parse_block().to_option())
+    fn next(&mut self) -> Option<Block<'a>> {
+        loop {
+            let piece = match self.next_piece() {
+                Some(piece) => piece,
+                None => return None
+            };
+            self.record_span(&piece);
+            let block = match piece {
+                Piece::Start(tag, _) => { self.builder.start(&tag); None }
+                Piece::End(tag, _) => self.builder.end(tag),
+                Piece::Leaf(block, _, _) => self.builder.push_block(block)
+            };
+            if block.is_some() {
+                return block;
+            }
+        }
+    }
+}
+
+/// An iterator of events of a document, see `MarkdownParser::events()`.
+pub struct MarkdownEvents<'a> {
+    parser: MarkdownParser<'a>,
+    // events of a leaf block
+    queue: VecDeque<Event<'a>>
+}
+
+impl<'a> MarkdownEvents<'a> {
+    /// Returns the parser, for example to look at reference definitions.
+    #[inline]
+    pub fn parser(&self) -> &MarkdownParser<'a> {
+        &self.parser
+    }
+}
+
+impl<'a> Iterator for MarkdownEvents<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Event<'a>> {
+        if self.queue.is_empty() {
+            match self.parser.next_piece() {
+                Some(Piece::Start(tag, _)) => return Some(Event::Start(tag)),
+                Some(Piece::End(tag, _)) => return Some(Event::End(tag)),
+                Some(Piece::Leaf(block, _, _)) => push_block_events(block, &mut self.queue),
+                None => return None
+            }
+        }
+        self.queue.pop_front()
     }
 }
 
 // private methods
 impl<'a> MarkdownParser<'a> {
-    fn fork<'b>(&self, buffer: &'b [u8]) -> MarkdownParser<'b> {
+    // parse_piece() returns NoParse only on a bug in the parser; then the rest
+    // of the input is returned as a paragraph instead of being dropped
+    fn next_piece(&self) -> Option<Piece<'a>> {
+        match self.parse_piece() {
+            Success(piece) => Some(piece),
+            End => None,
+            NoParse => {
+                debug_assert!(false, "parse_piece() returned NoParse at {}", self.cur.pos.get());
+                let start = self.cur.pos.get();
+                let rest = &self.cur.buf[start..];
+                self.cur.advance(rest.len());
+                let rest = rest.trim_right(|b: u8| b == b'\n' || b == b'\r');
+                if rest.is_empty() { return None }
+                Some(Piece::Leaf(Paragraph(vec![Chunk(decode_text(rest))]), start, start + rest.len()))
+            }
+        }
+    }
+
+    // a parser over the part of the current buffer from start to end
+    fn fork(&self, start: usize, end: usize) -> MarkdownParser<'a> {
+        self.fork_with_origin(&self.cur.buf[start..end], self.origin.clone(), self.origin_base + start)
+    }
+
+    fn fork_with_origin<'b>(&self, buffer: &'b [u8], origin: Rc<Vec<(usize, usize)>>,
+                            base: usize) -> MarkdownParser<'b> {
         MarkdownParser {
             cur: Cursor::new(buffer),
             event_queue: RefCell::new(VecDeque::new()),
+            builder: BlockBuilder::new(),
+            open_blocks: RefCell::new(OpenBlocks::new()),
             config: self.config,
-            link_map: None
+            inline_depth: self.inline_depth,
+            skip_plain_text: self.skip_plain_text,
+            unclosed: RefCell::new(HashMap::new()),
+            overflow: Cell::new(&[]),
+            source: buffer,
+            spans: None,
+            open_spans: Vec::new(),
+            link_map: self.link_map.clone(),
+            reference_log: self.reference_log.clone(),
+            origin: origin,
+            origin_base: base
+        }
+    }
+
+    // whether a delimiter ending at pos is known to have no closing one;
+    // without this, every opener of a run of them would scan to the end
+    fn is_unclosed(&self, delim: (u8, usize), pos: usize) -> bool {
+        self.unclosed.borrow().get(&delim).map(|&from| pos >= from).unwrap_or(false)
+    }
+
+    fn set_unclosed(&self, delim: (u8, usize), from: usize) {
+        let mut unclosed = self.unclosed.borrow_mut();
+        let from = unclosed.get(&delim).map(|&f| cmp::min(f, from)).unwrap_or(from);
+        unclosed.insert(delim, from);
+    }
+
+    // translates an offset in the current buffer to an offset in the source
+    fn source_pos(&self, pos: usize) -> usize {
+        let offset = self.origin_base + pos;
+        // the number of entries at or before the offset; the comparison
+        // never gives Equal, so the search always fails at that index
+        let n = match self.origin.binary_search_by(|&(b, _)| {
+            if b <= offset { Ordering::Less } else { Ordering::Greater }
+        }) {
+            Ok(i) | Err(i) => i
+        };
+        if n == 0 { return pos }
+        let (b, s) = self.origin[n - 1];
+        s + (offset - b)
+    }
+
+    // LF, CR and CRLF all end a line; after a CR has been consumed, this
+    // consumes the LF following it, if any
+    #[inline]
+    fn complete_line_end(&self) {
+        if self.cur.peek_prev() == Some(b'\r') &&
+           self.cur.current_byte() == Some(b'\n') {
+            self.cur.next();
+        }
+    }
+
+    fn try_read_line_end(&self) -> ParseResult<()> {
+        match self.cur.next_byte() {
+            Some(b'\n') => Success(()),
+            Some(b'\r') => { self.complete_line_end(); Success(()) }
+            Some(_) => { self.cur.prev(); NoParse },
+            None => End
         }
     }
 
@@ -310,26 +661,24 @@ impl<'a> MarkdownParser<'a> {
         let m = self.cur.mark();
         loop {
             match opt_ret_end!(self.cur.next_byte()) {
-                b' ' => {}
-                b'\n' => { m.cancel(); return Success(()) }
+                b' ' | b'\t' => {}
+                b'\n' | b'\r' => { self.complete_line_end(); m.cancel(); return Success(()) }
                 _ => return NoParse
             }
         }
     }
 
+    // skips up to 3 columns of indentation
     fn try_skip_initial_spaces(&self) -> ParseResult<()> {
-        let mut n: u8 = 0;
         let m = self.cur.mark();
-        while self.cur.available() {
-            if n >= 4 {
-                return NoParse;
-            }
-            match *self.cur {
-                b' ' => { n += 1; self.cur.next(); }  // increase counter and continue
-                _ => { m.cancel(); return Success(()); },  // not a space and less than 4 spaces
-            }
+        if self.cur.skip_indent(4) == 4 {
+            return NoParse;
         }
-        End
+        if !self.cur.available() {
+            return End;
+        }
+        m.cancel();
+        Success(())
     }
 
     fn try_read_char(&self, expected: u8) -> ParseResult<()> {
@@ -340,6 +689,12 @@ impl<'a> MarkdownParser<'a> {
         }
     }
 
+    // whether f succeeds at the current position; nothing is consumed
+    fn lookahead<T, F>(&self, f: F) -> bool where F: FnOnce() -> ParseResult<T> {
+        let _m = self.cur.mark();
+        f().is_success()
+    }
+
     fn lookahead_chars(&self, mut n: usize, c: u8) -> bool {
         let _m = self.cur.mark();
         while n > 0 && self.cur.available() {
@@ -351,16 +706,36 @@ impl<'a> MarkdownParser<'a> {
         n == 0
     }
 
+    // line endings are written as LF unless they are configured to be kept;
+    // the rest of a partially consumed tab is written as spaces
     fn read_line_to(&self, dest: &mut Vec<u8>) -> ParseResult<()> {
         if !self.cur.available() { return End }
 
+        if self.cur.partial.get() > 0 {
+            dest.extend(iter::repeat(b' ').take(tab_width(self.cur.column())));
+            self.cur.next();
+            if !self.cur.available() { return Success(()) }
+        }
+
         while {
             let c = *self.cur; self.cur.next();
-            dest.push(c);
 
             if c == b'\n' {
+                dest.push(c);
+                return Success(());
+            }
+            if c == b'\r' {
+                let crlf = self.cur.current_byte() == Some(b'\n');
+                if self.config.keep_line_endings {
+                    dest.push(b'\r');
+                    if crlf { dest.push(b'\n'); }
+                } else {
+                    dest.push(b'\n');
+                }
+                if crlf { self.cur.next(); }
                 return Success(());
             }
+            dest.push(c);
             
             self.cur.available() 
         } {}
@@ -376,6 +751,10 @@ impl<'a> MarkdownParser<'a> {
             if c == b'\n' {
                 return Success(())
             }
+            if c == b'\r' {
+                self.complete_line_end();
+                return Success(())
+            }
 
             self.cur.available()
         } {}
@@ -392,7 +771,7 @@ impl<'a> MarkdownParser<'a> {
             if m.matches(c) {
                 self.cur.next();
             } else {
-                return match self.cur.slice_until_now_from(pm) {
+                return match self.cur.slice_to_now_from(pm) {
                     s if s.is_empty() => NoParse,
                     s                 => Success(s)
                 };
@@ -400,7 +779,7 @@ impl<'a> MarkdownParser<'a> {
 
             self.cur.available()
         } {}
-        Success(self.cur.slice_until_now_from(pm))
+        Success(self.cur.slice_to_now_from(pm))
     }
 
     fn skip<M: ByteMatcher>(&self, mut m: M) -> ParseResult<()> {
@@ -422,23 +801,51 @@ impl<'a> MarkdownParser<'a> {
 
     #[inline]
     fn skip_spaces(&self) -> ParseResult<()> { 
-        self.skip(b' ')
+        self.skip([b' ', b'\t'].as_slice())
     }
 
     #[inline]
     fn skip_spaces_and_newlines(&self) -> ParseResult<()> { 
-        self.skip([b' ', b'\n'].as_slice())
+        self.skip([b' ', b'\t', b'\n', b'\r'].as_slice())
     }
 
-    #[inline]
-    fn fix_links<F: FixLinks>(&self, mut fl: F) -> F {
-        fl.fix_links_opt(self.link_map.as_ref());
-        fl
+    // the first definition of a label wins; pos is the position of the definition start
+    fn add_link(&self, ld: LinkDescription, pos: usize) {
+        let key = normalize_label(ld.id.as_slice());
+        self.reference_log.borrow_mut().add_definition(key.clone(), ld.id.clone(), self.source_pos(pos));
+
+        let mut link_map = self.link_map.borrow_mut();
+        if !link_map.contains_key(&key) {
+            link_map.insert(key, ld);
+        }
+    }
+
+    // pos is the position of the reference start
+    fn add_reference(&self, label: &str, pos: usize) {
+        self.reference_log.borrow_mut()
+            .add_reference(normalize_label(label), label.to_string(), self.source_pos(pos));
+    }
+
+    fn record_span(&mut self, piece: &Piece<'a>) {
+        let spans = match self.spans {
+            Some(ref mut spans) => spans,
+            None => return
+        };
+        match *piece {
+            Piece::Start(_, start) => {
+                self.open_spans.push(spans.len());
+                spans.push((start, start));
+            }
+            Piece::End(_, end) => if let Some(i) = self.open_spans.pop() {
+                spans[i].1 = end;
+            },
+            Piece::Leaf(_, start, end) => spans.push((start, end))
+        }
     }
 
     #[inline]
-    fn enqueue_event(&self, block: Block) {
-        self.event_queue.borrow_mut().push_back(block)
+    fn enqueue(&self, piece: Piece<'a>) {
+        self.event_queue.borrow_mut().push_back(piece)
     }
 }
 
@@ -486,15 +893,6 @@ impl<T> ParseResult<T> {
         }
     }
 
-    #[inline]
-    fn unwrap(self) -> T {
-        match self {
-            Success(r) => r,
-            End => panic!("End unwrap"),
-            NoParse => panic!("NoParse unwrap")
-        }
-    }
-
     #[inline]
     fn is_success(&self) -> bool {
         match *self {
@@ -510,12 +908,4 @@ impl<T> ParseResult<T> {
             _ => false
         }
     }
-
-    fn to_option(self) -> Option<T> {
-        match self {
-            Success(r) => Some(r),
-            End => None,
-            NoParse => panic!("programming error, NoParse is converted to result")
-        }
-    }
 }
@@ -0,0 +1,319 @@
+use std::io::{self, BufRead, Read};
+use std::collections::{HashMap, VecDeque};
+use std::cell::{RefCell, Ref};
+use std::rc::Rc;
+use std::mem;
+use std::borrow::Cow;
+
+use parser::{MarkdownParser, MarkdownConfig, Diagnostic, Position};
+use parser::diagnostics::{ReferenceLog, position_after};
+use tokens::*;
+use events::Events;
+use util::{CharOps, decode_text};
+
+// size of text chunks returned past the size limit
+const OVERFLOW_CHUNK: usize = 64 * 1024;
+
+/// A parser which reads its input incrementally.
+///
+/// Input is read line by line and buffered only until the top-level block
+/// it belongs to is certainly closed: when a blank line is followed by a line
+/// which can't continue any open block, or after an ATX heading, a thematic
+/// break or the closing fence of a code block started at the beginning of a
+/// line. The buffered part is then parsed, and its blocks are returned by the
+/// iterator.
+///
+/// The part of the input past `max_document_size`, from the line the limit
+/// falls on, is not buffered: it is returned as paragraphs of plain text, each
+/// of at most 64 KiB.
+///
+/// Blocks are detached from the input. Like with `MarkdownParser`, references
+/// in them are not resolved because their definitions may follow them; use
+/// `link_map()` to resolve them. Diagnostics and their positions refer to the
+/// whole input.
+pub struct StreamingParser<R> {
+    input: R,
+    config: MarkdownConfig,
+    link_map: Rc<RefCell<LinkMap>>,
+    reference_log: Rc<RefCell<ReferenceLog>>,
+
+    // lines of the current top-level block
+    buf: Vec<u8>,
+    // bytes of the input already parsed
+    parsed: usize,
+    // the position of the start of buf in the input
+    position: Position,
+    blocks: VecDeque<Block<'static>>,
+
+    // the opening fence of an unclosed fenced code block, and whether it
+    // starts at the beginning of the line
+    fence: Option<(u8, usize, bool)>,
+    after_blank: bool,
+    // whether the size limit is reached; buf then holds text not returned yet
+    overflow: bool,
+    error: Option<io::Error>,
+    done: bool
+}
+
+impl<R: BufRead> StreamingParser<R> {
+    pub fn new(input: R) -> StreamingParser<R> {
+        StreamingParser {
+            input: input,
+            config: MarkdownConfig::default(),
+            link_map: Rc::new(RefCell::new(HashMap::new())),
+            reference_log: Rc::new(RefCell::new(ReferenceLog::new())),
+            buf: Vec::new(),
+            parsed: 0,
+            position: Position { offset: 0, line: 1, column: 1 },
+            blocks: VecDeque::new(),
+            fence: None,
+            after_blank: false,
+            overflow: false,
+            error: None,
+            done: false
+        }
+    }
+
+    #[inline]
+    pub fn with_config(mut self, config: MarkdownConfig) -> StreamingParser<R> {
+        self.config = config;
+        self
+    }
+
+    /// Returns reference definitions encountered so far, keyed by normalized label.
+    #[inline]
+    pub fn link_map(&self) -> Ref<LinkMap> {
+        self.link_map.borrow()
+    }
+
+    /// Returns diagnostics about link references in the input parsed so far.
+    /// Like with `MarkdownParser`, they are complete only after the iteration
+    /// is finished.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.reference_log.borrow().diagnostics(&*self.link_map.borrow(), &[])
+    }
+
+    /// Returns the error which stopped reading the input, if any.
+    #[inline]
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    #[inline]
+    pub fn events(self) -> Events<'static, StreamingParser<R>> {
+        Events::new(self)
+    }
+
+    // reads lines until the buffered block is closed or the input ends; input
+    // with CR line endings only is buffered as a whole
+    fn fill(&mut self) {
+        if self.overflow {
+            return self.fill_overflow();
+        }
+        loop {
+            // a line is read only up to the size limit
+            let room = self.config.max_document_size.saturating_sub(self.parsed + self.buf.len());
+            let mut line = Vec::new();
+            match (&mut self.input).take(room.saturating_add(1) as u64).read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => { self.error = Some(e); break }
+            }
+
+            if line.len() > room {
+                self.flush();
+                self.overflow = true;
+                self.buf = line;
+                return self.fill_overflow();
+            }
+            let closes = self.closes_block(line.as_slice());
+            if closes {
+                self.flush();
+            }
+            let completes = self.completes_block(line.as_slice());
+            self.push_line(line);
+            if completes {
+                self.flush();
+            }
+            if closes || completes {
+                return;
+            }
+        }
+
+        self.done = true;
+        self.flush();
+    }
+
+    // returns the next piece of the input past the size limit as plain text
+    fn fill_overflow(&mut self) {
+        let mut text = mem::replace(&mut self.buf, Vec::new());
+        while text.len() < OVERFLOW_CHUNK {
+            let limit = (OVERFLOW_CHUNK - text.len()) as u64;
+            match (&mut self.input).take(limit).read_until(b'\n', &mut text) {
+                Ok(0) => { self.done = true; break }
+                Ok(_) => {}
+                Err(e) => { self.error = Some(e); self.done = true; break }
+            }
+        }
+
+        // a character or a CRLF split by the chunk end goes to the next chunk
+        if !self.done {
+            let end = complete_prefix(text.as_slice());
+            self.buf = text[end..].to_vec();
+            text.truncate(end);
+        }
+        if !text.is_empty() {
+            let content = decode_text(text.as_slice()).into_owned();
+            self.blocks.push_back(Paragraph(vec![Chunk(Cow::Owned(content))]));
+        }
+    }
+
+    // a line closes all open blocks if it follows a blank line, is not
+    // indented and does not continue a list or a fenced code block
+    fn closes_block(&self, line: &[u8]) -> bool {
+        if self.buf.is_empty() || !self.after_blank || self.fence.is_some() {
+            return false;
+        }
+        match line.first() {
+            Some(&b' ') | Some(&b'\t') | Some(&b'\n') | Some(&b'\r') | None => false,
+            Some(_) => !is_list_item(line)
+        }
+    }
+
+    // a line closes all open blocks together with itself if it is an ATX
+    // heading or a thematic break at the beginning of the line, or closes a
+    // fenced code block which started at the beginning of the line; no
+    // following line can continue them
+    fn completes_block(&self, line: &[u8]) -> bool {
+        match (self.fence, code_fence(line)) {
+            (Some((oc, on, true)), Some((c, n, true))) => c == oc && n >= on && line[0] == c,
+            (Some(_), _) => false,
+            (None, _) => is_atx_heading(line) || is_thematic_break(line)
+        }
+    }
+
+    fn push_line(&mut self, line: Vec<u8>) {
+        self.after_blank = line.iter().all(|&b| b == b' ' || b == b'\t' || b.is_line_end());
+
+        match (self.fence, code_fence(line.as_slice())) {
+            (None, Some((c, n, _))) => self.fence = Some((c, n, line[0] == c)),
+            (Some((oc, on, _)), Some((c, n, true))) if c == oc && n >= on => self.fence = None,
+            _ => {}
+        }
+
+        self.buf.extend(line.into_iter());
+    }
+
+    fn flush(&mut self) {
+        if self.buf.is_empty() { return }
+        let buf = mem::replace(&mut self.buf, Vec::new());
+
+        // the size limit applies to the whole input
+        let config = MarkdownConfig {
+            max_document_size: self.config.max_document_size.saturating_sub(self.parsed),
+            .. self.config
+        };
+        self.parsed += buf.len();
+
+        let mut p = MarkdownParser::new(buf.as_slice()).with_config(config);
+        p.link_map = self.link_map.clone();
+        p.reference_log = self.reference_log.clone();
+        p.origin = Rc::new(vec![(0, self.position.offset)]);
+        self.blocks.extend(p.map(|b| b.into_owned()));
+
+        // the buffer is dropped, so positions of its references are fixed now
+        self.reference_log.borrow_mut().locate(buf.as_slice(), self.position);
+        self.position = position_after(buf.as_slice(), self.position);
+    }
+}
+
+impl<R: BufRead> Iterator for StreamingParser<R> {
+    type Item = Block<'static>;
+
+    fn next(&mut self) -> Option<Block<'static>> {
+        loop {
+            if let Some(block) = self.blocks.pop_front() {
+                return Some(block);
+            }
+            if self.done {
+                return None;
+            }
+            self.fill();
+        }
+    }
+}
+
+// returns the length of the longest prefix which does not end inside of a
+// UTF-8 sequence or between CR and LF
+fn complete_prefix(s: &[u8]) -> usize {
+    let mut end = s.len();
+    if end > 0 && s[end - 1] == b'\r' {
+        return end - 1;
+    }
+    // look for the first byte of the last sequence
+    let lead = s.iter().rev().take(4).position(|&b| b & 0xC0 != 0x80);
+    if let Some(i) = lead {
+        let b = s[end - 1 - i];
+        let len = if b < 0x80 { 1 } else if b >= 0xF0 { 4 } else if b >= 0xE0 { 3 } else { 2 };
+        if i + 1 < len {
+            end -= i + 1;
+        }
+    }
+    end
+}
+
+// returns fence character, fence length and whether the rest of the line is
+// blank, i.e. the fence can close a block
+fn code_fence(line: &[u8]) -> Option<(u8, usize, bool)> {
+    let indent = line.iter().take_while(|&&b| b == b' ').count();
+    if indent > 3 { return None }
+    let line = &line[indent..];
+
+    let c = match line.first() {
+        Some(&c) if c == b'`' || c == b'~' => c,
+        _ => return None
+    };
+    let n = line.iter().take_while(|&&b| b == c).count();
+    if n < 3 { return None }
+
+    let blank = line[n..].iter().all(|&b| b == b' ' || b.is_line_end());
+    Some((c, n, blank))
+}
+
+fn is_atx_heading(line: &[u8]) -> bool {
+    let n = line.iter().take_while(|&&b| b == b'#').count();
+    if n == 0 || n > 6 { return false }
+    match line.get(n) {
+        Some(&b' ') | Some(&b'\t') | Some(&b'\n') | Some(&b'\r') | None => true,
+        _ => false
+    }
+}
+
+fn is_thematic_break(line: &[u8]) -> bool {
+    let c = match line.first() {
+        Some(&c) if c == b'*' || c == b'-' || c == b'_' => c,
+        _ => return false
+    };
+    let rest = line.iter().filter(|&&b| !b.is_line_end());
+    let mut n = 0;
+    for &b in rest {
+        if b == c { n += 1 } else if b != b' ' && b != b'\t' { return false }
+    }
+    n >= 3
+}
+
+fn is_list_item(line: &[u8]) -> bool {
+    let digits = line.iter().take_while(|&&b| b >= b'0' && b <= b'9').count();
+    let marker = match (digits, line.first()) {
+        (0, Some(&b'-')) | (0, Some(&b'*')) | (0, Some(&b'+')) => 1,
+        (0, _) => return false,
+        (n, _) => match line.get(n) {
+            Some(&b'.') | Some(&b')') => n + 1,
+            _ => return false
+        }
+    };
+    match line.get(marker) {
+        Some(&b' ') | Some(&b'\t') | Some(&b'\n') | Some(&b'\r') | None => true,
+        _ => false
+    }
+}
@@ -0,0 +1,400 @@
+//! ANSI terminal renderer.
+//!
+//! Produces text for terminals: emphasis is rendered in italic, strong emphasis
+//! in bold, headings are colored according to their level, code blocks are
+//! indented and block quotes are marked with a bar. Links are emitted as OSC 8
+//! hyperlinks. Without colors no escape sequences are emitted at all, and link
+//! destinations are written after the link text instead.
+//!
+//! Control characters other than tabs and line feeds are removed from the
+//! document text, so it can't emit escape sequences of its own, and links
+//! whose destinations contain control characters are not made hyperlinks.
+
+use std::iter;
+use std::borrow::Cow;
+
+use tokens::*;
+
+#[derive(Copy, Clone)]
+pub struct AnsiStyle {
+    /// Paragraphs are wrapped at this width; 0 keeps line breaks as they are.
+    pub width: usize,
+    pub colors: bool,
+    /// Whether links are emitted as OSC 8 hyperlinks; ignored without colors.
+    pub hyperlinks: bool
+}
+
+impl AnsiStyle {
+    #[inline]
+    pub fn default() -> AnsiStyle {
+        AnsiStyle {
+            width: 80,
+            colors: true,
+            hyperlinks: true
+        }
+    }
+}
+
+impl_setters! { AnsiStyle;
+    width: usize,
+    colors: bool,
+    hyperlinks: bool
+}
+
+#[inline]
+pub fn to_ansi<'a>(doc: &Document<'a>) -> String {
+    to_ansi_with_style(doc, AnsiStyle::default())
+}
+
+pub fn to_ansi_with_style<'a>(doc: &Document<'a>, style: AnsiStyle) -> String {
+    let r = Renderer { style: style };
+    let mut result = String::new();
+    for line in r.render_document(doc, style.width).into_iter() {
+        result.push_str(line.as_slice());
+        result.push('\n');
+    }
+    result
+}
+
+const RESET: &'static str = "\x1b[0m";
+const LINK_END: &'static str = "\x1b]8;;\x1b\\";
+
+struct Renderer {
+    style: AnsiStyle
+}
+
+impl Renderer {
+    // width is the available line width, 0 means no wrapping
+    fn render_document<'a>(&self, doc: &Document<'a>, width: usize) -> Vec<String> {
+        let mut result = Vec::new();
+        for block in doc.iter() {
+            let lines = self.render_block(block, width);
+            if lines.is_empty() { continue }
+            if !result.is_empty() {
+                result.push(String::new());
+            }
+            result.extend(lines.into_iter());
+        }
+        result
+    }
+
+    fn render_block<'a>(&self, block: &Block<'a>, width: usize) -> Vec<String> {
+        match *block {
+            Heading { level, ref content } => {
+                let mut pieces = Vec::new();
+                if self.style.colors {
+                    pieces.push(Piece::Open(Attr::Heading(level)));
+                } else {
+                    let marker: String = iter::repeat('#').take(level).collect();
+                    pieces.push(Piece::Text(marker));
+                    pieces.push(Piece::Space);
+                }
+                self.render_text(content, &mut pieces);
+                if self.style.colors {
+                    pieces.push(Piece::Close);
+                }
+                layout(&pieces, width)
+            }
+
+            BlockQuote(ref content) => {
+                let bar = if self.style.colors { "\x1b[90m│\x1b[0m" } else { "│" };
+                self.render_document(content, narrow(width, 2)).into_iter()
+                    .map(|line| if line.is_empty() { bar.to_string() } else { format!("{} {}", bar, line) })
+                    .collect()
+            }
+
+            BlockCode { ref content, .. } =>
+                content.trim_right_matches('\n').split('\n')
+                    .map(|line| strip_controls(line))
+                    .map(|line| if self.style.colors && !line.is_empty() {
+                        format!("    {}{}{}", Attr::Code.sgr(), line, RESET)
+                    } else {
+                        format!("    {}", line)
+                    })
+                    .collect(),
+
+            // raw HTML can't be shown in a terminal
+            HtmlBlock(_) => Vec::new(),
+
+            OrderedList { start_index, ref items, .. } => {
+                let mut result = Vec::new();
+                for (i, item) in items.iter().enumerate() {
+                    let marker = format!("{}. ", start_index + i);
+                    result.extend(self.render_item(marker.as_slice(), item, width).into_iter());
+                }
+                result
+            }
+
+            UnorderedList { ref items, .. } => {
+                let mut result = Vec::new();
+                for item in items.iter() {
+                    result.extend(self.render_item("• ", item, width).into_iter());
+                }
+                result
+            }
+
+            Paragraph(ref content) => {
+                let mut pieces = Vec::new();
+                self.render_text(content, &mut pieces);
+                layout(&pieces, width)
+            }
+
+            HorizontalRule => {
+                let n = if width == 0 { 40 } else { width };
+                vec![iter::repeat('─').take(n).collect()]
+            }
+        }
+    }
+
+    fn render_item<'a>(&self, marker: &str, item: &Document<'a>, width: usize) -> Vec<String> {
+        let n = marker.chars().count();
+        let indent: String = iter::repeat(' ').take(n).collect();
+
+        let mut result = Vec::new();
+        for line in self.render_document(item, narrow(width, n)).into_iter() {
+            if result.is_empty() {
+                result.push(format!("{}{}", marker, line));
+            } else if line.is_empty() {
+                result.push(line);
+            } else {
+                result.push(format!("{}{}", indent, line));
+            }
+        }
+        if result.is_empty() {
+            result.push(marker.trim_right().to_string());
+        }
+        result
+    }
+
+    fn render_text<'a>(&self, text: &Text<'a>, out: &mut Vec<Piece>) {
+        for inline in text.iter() {
+            match *inline {
+                LineBreak => out.push(Piece::HardBreak),
+
+                Chunk(ref s) => push_chunk(out, &**s),
+
+                Emphasis(ref content) => self.render_styled(Attr::Italic, content, out),
+
+                MoreEmphasis(ref content) => self.render_styled(Attr::Bold, content, out),
+
+                Code(ref s) => {
+                    if self.style.colors { out.push(Piece::Open(Attr::Code)); }
+                    push_chunk(out, &**s);
+                    if self.style.colors { out.push(Piece::Close); }
+                }
+
+                HtmlInline(_) => {}
+
+                Link { text: None, ref link, .. } => {
+                    let link = link.as_ref().map(|l| &**l).unwrap_or("");
+                    self.render_link(&vec![Chunk(Cow::Borrowed(link))], link, out);
+                }
+
+                Link { text: Some(ref text), ref link, .. } =>
+                    self.render_link(text, link.as_ref().map(|l| &**l).unwrap_or(""), out),
+
+                Image { ref alt, ref link, .. } =>
+                    self.render_link(alt, link.as_ref().map(|l| &**l).unwrap_or(""), out)
+            }
+        }
+    }
+
+    fn render_styled<'a>(&self, attr: Attr, text: &Text<'a>, out: &mut Vec<Piece>) {
+        if self.style.colors { out.push(Piece::Open(attr)); }
+        self.render_text(text, out);
+        if self.style.colors { out.push(Piece::Close); }
+    }
+
+    fn render_link<'a>(&self, text: &Text<'a>, link: &str, out: &mut Vec<Piece>) {
+        let hyperlink = !link.is_empty() && !link.chars().any(is_control);
+        if self.style.colors && self.style.hyperlinks && hyperlink {
+            out.push(Piece::Open(Attr::Link(link.to_string())));
+            self.render_text(text, out);
+            out.push(Piece::Close);
+            return;
+        }
+
+        self.render_text(text, out);
+        let link = strip_controls(link);
+        if !link.is_empty() && text_content(text).as_slice() != &*link {
+            out.push(Piece::Space);
+            out.push(Piece::Text(format!("<{}>", link)));
+        }
+    }
+}
+
+#[inline]
+fn narrow(width: usize, n: usize) -> usize {
+    if width == 0 { 0 } else if width > n { width - n } else { 1 }
+}
+
+#[derive(Clone, PartialEq)]
+enum Attr {
+    Heading(usize),
+    Bold,
+    Italic,
+    Code,
+    Link(String)
+}
+
+impl Attr {
+    fn sgr(&self) -> &'static str {
+        match *self {
+            Attr::Heading(1) => "\x1b[1;35m",
+            Attr::Heading(2) => "\x1b[1;34m",
+            Attr::Heading(3) => "\x1b[1;36m",
+            Attr::Heading(_) => "\x1b[1;32m",
+            Attr::Bold => "\x1b[1m",
+            Attr::Italic => "\x1b[3m",
+            Attr::Code => "\x1b[33m",
+            Attr::Link(_) => "\x1b[4m"
+        }
+    }
+}
+
+// rendered inline content; Open and Close do not take any space, Close ends
+// the innermost open attribute
+enum Piece {
+    Text(String),
+    Space,
+    SoftBreak,
+    HardBreak,
+    Open(Attr),
+    Close
+}
+
+fn push_chunk(out: &mut Vec<Piece>, s: &str) {
+    let mut word = String::new();
+    for c in s.chars() {
+        match c {
+            ' ' | '\n' => {
+                if !word.is_empty() {
+                    out.push(Piece::Text(word.clone()));
+                    word.clear();
+                }
+                out.push(if c == ' ' { Piece::Space } else { Piece::SoftBreak });
+            }
+            c if is_control(c) && c != '\t' => {}
+            c => word.push(c)
+        }
+    }
+    if !word.is_empty() {
+        out.push(Piece::Text(word));
+    }
+}
+
+// C0 and C1 control characters, ESC among them
+#[inline]
+fn is_control(c: char) -> bool {
+    c < ' ' || (c >= '\x7f' && c <= '\u{9f}')
+}
+
+// removes control characters but tabs, which are harmless in a terminal
+fn strip_controls(s: &str) -> Cow<str> {
+    if !s.chars().any(|c| is_control(c) && c != '\t') {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(s.chars().filter(|&c| !is_control(c) || c == '\t').collect())
+}
+
+// escape sequences which put the terminal into the state of active attributes
+fn push_state(out: &mut String, active: &[Attr]) {
+    for attr in active.iter() {
+        out.push_str(attr.sgr());
+    }
+    if let Some(&Attr::Link(ref link)) = active.iter().rev().find(|a| match **a { Attr::Link(_) => true, _ => false }) {
+        out.push_str(format!("\x1b]8;;{}\x1b\\", link).as_slice());
+    }
+}
+
+// every line ends with the terminal in its default state, so lines can be
+// prefixed freely
+fn end_line(out: &mut String, active: &[Attr]) {
+    if active.iter().any(|a| match *a { Attr::Link(_) => true, _ => false }) {
+        out.push_str(LINK_END);
+    }
+    if !active.is_empty() {
+        out.push_str(RESET);
+    }
+}
+
+// joins the pieces into lines no longer than width where possible;
+// width 0 keeps spaces and line breaks as they are
+fn layout(pieces: &[Piece], width: usize) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+    let mut pending_space = false;
+    let mut active: Vec<Attr> = Vec::new();
+
+    macro_rules! new_line {
+        () => ({
+            end_line(&mut line, active.as_slice());
+            result.push(line);
+            line = String::new();
+            push_state(&mut line, active.as_slice());
+            line_len = 0;
+        })
+    }
+
+    let mut i = 0;
+    while i < pieces.len() {
+        match pieces[i] {
+            Piece::Space if width == 0 => { line.push(' '); line_len += 1; }
+            Piece::SoftBreak if width == 0 => new_line!(),
+            Piece::Space | Piece::SoftBreak => pending_space = line_len > 0,
+            Piece::HardBreak => { new_line!(); pending_space = false; }
+
+            _ => {
+                // a word spans all pieces up to the next space or break
+                let mut j = i;
+                let mut n = 0;
+                while j < pieces.len() {
+                    match pieces[j] {
+                        Piece::Text(ref s) => n += s.chars().count(),
+                        Piece::Open(_) | Piece::Close => {}
+                        _ => break
+                    }
+                    j += 1;
+                }
+
+                if pending_space && width > 0 && line_len + 1 + n > width {
+                    new_line!();
+                } else if pending_space {
+                    line.push(' ');
+                    line_len += 1;
+                }
+                pending_space = false;
+
+                for piece in pieces[i..j].iter() {
+                    match *piece {
+                        Piece::Text(ref s) => line.push_str(s.as_slice()),
+                        Piece::Open(ref attr) => {
+                            line.push_str(attr.sgr());
+                            if let Attr::Link(ref link) = *attr {
+                                line.push_str(format!("\x1b]8;;{}\x1b\\", link).as_slice());
+                            }
+                            active.push(attr.clone());
+                        }
+                        Piece::Close => {
+                            if let Some(Attr::Link(_)) = active.pop() {
+                                line.push_str(LINK_END);
+                            }
+                            line.push_str(RESET);
+                            push_state(&mut line, active.as_slice());
+                        }
+                        _ => {}
+                    }
+                }
+                line_len += n;
+                i = j;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    end_line(&mut line, active.as_slice());
+    result.push(line);
+    result
+}
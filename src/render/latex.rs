@@ -0,0 +1,286 @@
+//! LaTeX renderer.
+//!
+//! Headings become sectioning commands from `\section` down to
+//! `\subparagraph`, code blocks are `verbatim` or `lstlisting` environments
+//! (or lines of `\verb` if the code contains the end of the environment),
+//! lists are `itemize` and `enumerate` environments and block quotes are
+//! `quote` environments. Links need the `hyperref` package and images the
+//! `graphicx` package; both are loaded by the preamble of a standalone
+//! document.
+
+use tokens::*;
+
+#[derive(Copy, Clone)]
+pub struct LatexStyle {
+    /// Whether a complete document with a preamble is produced instead of a
+    /// fragment to be included into another document.
+    pub standalone: bool,
+    /// Whether code blocks are `lstlisting` environments, with the language
+    /// taken from their tag, instead of `verbatim` ones.
+    pub listings: bool
+}
+
+impl LatexStyle {
+    #[inline]
+    pub fn default() -> LatexStyle {
+        LatexStyle {
+            standalone: false,
+            listings: false
+        }
+    }
+}
+
+impl_setters! { LatexStyle;
+    standalone: bool,
+    listings: bool
+}
+
+#[inline]
+pub fn to_latex<'a>(doc: &Document<'a>) -> String {
+    to_latex_with_style(doc, LatexStyle::default())
+}
+
+pub fn to_latex_with_style<'a>(doc: &Document<'a>, style: LatexStyle) -> String {
+    let mut r = Renderer {
+        out: String::new(),
+        style: style,
+        enum_depth: 0
+    };
+
+    if style.standalone {
+        r.out.push_str("\\documentclass{article}\n");
+        r.out.push_str("\\usepackage[utf8]{inputenc}\n");
+        r.out.push_str("\\usepackage[T1]{fontenc}\n");
+        r.out.push_str("\\usepackage{graphicx}\n");
+        if style.listings {
+            r.out.push_str("\\usepackage{listings}\n");
+        }
+        r.out.push_str("\\usepackage{hyperref}\n");
+        r.out.push_str("\n\\begin{document}\n\n");
+    }
+
+    r.render_document(doc);
+
+    if style.standalone {
+        r.out.push_str("\n\\end{document}\n");
+    }
+    r.out
+}
+
+// enumerate counters for each nesting level
+const ENUM_COUNTERS: [&'static str; 4] = ["enumi", "enumii", "enumiii", "enumiv"];
+
+struct Renderer {
+    out: String,
+    style: LatexStyle,
+    enum_depth: usize
+}
+
+impl Renderer {
+    fn render_document<'a>(&mut self, doc: &Document<'a>) {
+        for (i, block) in doc.iter().enumerate() {
+            if i > 0 {
+                self.out.push('\n');
+            }
+            self.render_block(block);
+        }
+    }
+
+    fn render_block<'a>(&mut self, block: &Block<'a>) {
+        match *block {
+            Heading { level, ref content } => {
+                let command = match level {
+                    1 => "section",
+                    2 => "subsection",
+                    3 => "subsubsection",
+                    4 => "paragraph",
+                    _ => "subparagraph"
+                };
+                self.out.push('\\');
+                self.out.push_str(command);
+                self.out.push('{');
+                self.render_text(content);
+                self.out.push_str("}\n");
+            }
+
+            BlockQuote(ref content) => {
+                self.out.push_str("\\begin{quote}\n");
+                self.render_document(content);
+                self.out.push_str("\\end{quote}\n");
+            }
+
+            BlockCode { ref tag, ref content } => {
+                let env = if self.style.listings { "lstlisting" } else { "verbatim" };
+
+                // the environment can't contain its own end
+                if content.contains(format!("\\end{{{}}}", env).as_slice()) {
+                    self.out.push_str("\\begin{flushleft}\n");
+                    push_verb_lines(&mut self.out, &**content);
+                    self.out.push_str("\\end{flushleft}\n");
+                    return;
+                }
+
+                self.out.push_str("\\begin{");
+                self.out.push_str(env);
+                self.out.push('}');
+                if self.style.listings {
+                    let language: String = tag.as_ref().map(|t| &**t).unwrap_or("").chars()
+                        .filter(|c| c.is_alphanumeric() || *c == '+' || *c == '-')
+                        .collect();
+                    if !language.is_empty() {
+                        self.out.push_str(format!("[language={}]", language).as_slice());
+                    }
+                }
+                self.out.push('\n');
+                self.out.push_str(&**content);
+                if !content.is_empty() && !content.ends_with("\n") {
+                    self.out.push('\n');
+                }
+                self.out.push_str("\\end{");
+                self.out.push_str(env);
+                self.out.push_str("}\n");
+            }
+
+            HtmlBlock(_) => {}
+
+            OrderedList { start_index, ref items, .. } => {
+                self.out.push_str("\\begin{enumerate}\n");
+                if start_index != 1 && self.enum_depth < ENUM_COUNTERS.len() {
+                    self.out.push_str(format!("\\setcounter{{{}}}{{{}}}\n",
+                                              ENUM_COUNTERS[self.enum_depth],
+                                              start_index as isize - 1).as_slice());
+                }
+                self.enum_depth += 1;
+                self.render_items(items);
+                self.enum_depth -= 1;
+                self.out.push_str("\\end{enumerate}\n");
+            }
+
+            UnorderedList { ref items, .. } => {
+                self.out.push_str("\\begin{itemize}\n");
+                self.render_items(items);
+                self.out.push_str("\\end{itemize}\n");
+            }
+
+            Paragraph(ref content) => {
+                self.render_text(content);
+                self.out.push('\n');
+            }
+
+            HorizontalRule => self.out.push_str("\\medskip\n\\hrule\n\\medskip\n")
+        }
+    }
+
+    fn render_items<'a>(&mut self, items: &Vec<Document<'a>>) {
+        for item in items.iter() {
+            // braces keep a [ at the start of the item from being read as
+            // the optional argument
+            self.out.push_str("\\item{} ");
+            if item.is_empty() {
+                self.out.push('\n');
+            }
+            self.render_document(item);
+        }
+    }
+
+    fn render_text<'a>(&mut self, text: &Text<'a>) {
+        for inline in text.iter() {
+            match *inline {
+                LineBreak => self.out.push_str("\\\\\n"),
+
+                Chunk(ref s) => push_escaped(&mut self.out, &**s),
+
+                Emphasis(ref content) => {
+                    self.out.push_str("\\emph{");
+                    self.render_text(content);
+                    self.out.push('}');
+                }
+
+                MoreEmphasis(ref content) => {
+                    self.out.push_str("\\textbf{");
+                    self.render_text(content);
+                    self.out.push('}');
+                }
+
+                Code(ref s) => {
+                    self.out.push_str("\\texttt{");
+                    push_escaped(&mut self.out, &**s);
+                    self.out.push('}');
+                }
+
+                HtmlInline(_) => {}
+
+                Link { text: None, ref link, .. } => {
+                    self.out.push_str("\\url{");
+                    push_url(&mut self.out, link.as_ref().map(|l| &**l).unwrap_or(""));
+                    self.out.push('}');
+                }
+
+                Link { text: Some(ref text), ref link, .. } => {
+                    self.out.push_str("\\href{");
+                    push_url(&mut self.out, link.as_ref().map(|l| &**l).unwrap_or(""));
+                    self.out.push_str("}{");
+                    self.render_text(text);
+                    self.out.push('}');
+                }
+
+                Image { ref link, .. } => {
+                    self.out.push_str("\\includegraphics{");
+                    push_url(&mut self.out, link.as_ref().map(|l| &**l).unwrap_or(""));
+                    self.out.push('}');
+                }
+            }
+        }
+    }
+}
+
+fn push_escaped(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => { out.push('\\'); out.push(c); }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '\\' => out.push_str("\\textbackslash{}"),
+            c => out.push(c)
+        }
+    }
+}
+
+// writes every line of code as a \verb command, with a delimiter the line
+// does not contain; a line which contains all of them is escaped instead
+fn push_verb_lines(out: &mut String, s: &str) {
+    let lines: Vec<&str> = s.trim_right_matches('\n').split('\n').collect();
+    for (i, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            out.push_str("\\mbox{}");
+        } else {
+            match VERB_DELIMITERS.chars().find(|&d| !line.contains(d)) {
+                Some(d) => {
+                    out.push_str("\\verb");
+                    out.push(d);
+                    out.push_str(line);
+                    out.push(d);
+                }
+                None => {
+                    out.push_str("\\texttt{");
+                    push_escaped(out, line);
+                    out.push('}');
+                }
+            }
+        }
+        out.push_str(if i + 1 < lines.len() { "\\\\\n" } else { "\n" });
+    }
+}
+
+const VERB_DELIMITERS: &'static str = "|!+=@/\"';:-.,";
+
+// URLs are written almost verbatim by hyperref, only characters which
+// would break the argument are escaped
+fn push_url(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '%' | '#' | '\\' | '{' | '}' => { out.push('\\'); out.push(c); }
+            c => out.push(c)
+        }
+    }
+}
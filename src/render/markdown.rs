@@ -0,0 +1,595 @@
+//! Markdown renderer.
+//!
+//! Produces normalized Markdown: by default, ATX headings, fenced code blocks,
+//! `-` bullets, `*` emphasis and reference definitions collected at the end of
+//! the document; see `MarkdownStyle` for alternatives. Text is escaped only
+//! where it would otherwise be parsed as markup, so parsing the output gives
+//! the same token tree.
+
+use std::iter;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use tokens::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HeadingStyle {
+    /// `# Heading`.
+    Atx,
+    /// Underlined with `=` or `-`, for levels 1 and 2 only; other levels are ATX.
+    Setext
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ListNumbering {
+    /// Items are numbered sequentially from the list start index.
+    Incrementing,
+    /// Every item repeats the list start index, usually `1.`.
+    AllOnes
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LinkStyle {
+    /// Reference links stay references, inline links stay inline.
+    Preserve,
+    /// All links are inline, reference definitions are dropped.
+    Inline,
+    /// All links are references; definitions are generated for inline links.
+    Reference
+}
+
+#[derive(Copy, Clone)]
+pub struct MarkdownStyle {
+    pub heading_style: HeadingStyle,
+    pub bullet: char,
+    pub emphasis: char,
+    /// Paragraphs are wrapped at this width; 0 keeps line breaks as they are.
+    pub wrap_width: usize,
+    pub numbering: ListNumbering,
+    pub link_style: LinkStyle
+}
+
+impl MarkdownStyle {
+    #[inline]
+    pub fn default() -> MarkdownStyle {
+        MarkdownStyle {
+            heading_style: HeadingStyle::Atx,
+            bullet: '-',
+            emphasis: '*',
+            wrap_width: 0,
+            numbering: ListNumbering::Incrementing,
+            link_style: LinkStyle::Preserve
+        }
+    }
+}
+
+impl_setters! { MarkdownStyle;
+    heading_style: HeadingStyle,
+    bullet: char,
+    emphasis: char,
+    wrap_width: usize,
+    numbering: ListNumbering,
+    link_style: LinkStyle
+}
+
+/// Renders the document together with reference definitions from the link
+/// map, in the default style.
+#[inline]
+pub fn to_markdown<'a>(doc: &Document<'a>, link_map: &LinkMap) -> String {
+    to_markdown_with_style(doc, link_map, MarkdownStyle::default())
+}
+
+pub fn to_markdown_with_style<'a>(doc: &Document<'a>, link_map: &LinkMap, style: MarkdownStyle) -> String {
+    let mut r = Renderer {
+        link_map: link_map,
+        style: style,
+        generated: HashMap::new()
+    };
+    let mut result = r.render_document(doc, style.wrap_width, false);
+
+    let mut definitions: Vec<&LinkDescription> = match style.link_style {
+        LinkStyle::Inline => Vec::new(),
+        _ => link_map.values().chain(r.generated.values()).collect()
+    };
+    definitions.sort_by(|a, b| a.id.cmp(&b.id));
+    if !definitions.is_empty() && !result.is_empty() {
+        result.push('\n');
+    }
+    for ld in definitions.into_iter() {
+        result.push('[');
+        result.push_str(ld.id.as_slice());
+        result.push_str("]: ");
+        push_destination(&mut result, ld.link.as_slice());
+        if let Some(ref title) = ld.title {
+            result.push(' ');
+            push_title(&mut result, title.as_slice());
+        }
+        result.push('\n');
+    }
+
+    result
+}
+
+struct Renderer<'m> {
+    link_map: &'m LinkMap,
+    style: MarkdownStyle,
+    // definitions for inline links converted to references, by normalized label
+    generated: HashMap<String, LinkDescription>
+}
+
+impl<'m> Renderer<'m> {
+    // width is the available line width, 0 means no wrapping; blocks of a
+    // tight list item are not separated by blank lines
+    fn render_document<'a>(&mut self, doc: &Document<'a>, width: usize, tight: bool) -> String {
+        let mut result = String::new();
+        // a list right after a list of the same kind switches its markers,
+        // otherwise both would be read back as one list
+        let mut alternate = false;
+        for (i, block) in doc.iter().enumerate() {
+            if i > 0 {
+                if !tight {
+                    result.push('\n');
+                }
+                alternate = same_list_kind(&doc[i - 1], block) && !alternate;
+            }
+            let after_paragraph = tight && i > 0 && match doc[i - 1] {
+                Paragraph(_) => true,
+                _ => false
+            };
+            match *block {
+                // without a blank line, `---` and setext underlines would
+                // turn the paragraph into a heading
+                HorizontalRule if after_paragraph => result.push_str("***\n"),
+                Heading { .. } if after_paragraph => {
+                    let style = self.style;
+                    self.style.heading_style = HeadingStyle::Atx;
+                    self.render_block(block, width, alternate, &mut result);
+                    self.style = style;
+                }
+                _ => self.render_block(block, width, alternate, &mut result)
+            }
+        }
+        result
+    }
+
+    fn render_block<'a>(&mut self, block: &Block<'a>, width: usize, alternate: bool, out: &mut String) {
+        match *block {
+            Heading { level, ref content } => {
+                let mut pieces = Pieces::new();
+                self.render_text(content, &mut pieces);
+                let text = pieces.layout(0).replace("\n", " ");
+
+                if self.style.heading_style == HeadingStyle::Setext && level <= 2 && !text.is_empty() {
+                    let underline = if level == 1 { '=' } else { '-' };
+                    let n = text.chars().count();
+                    out.push_str(text.as_slice());
+                    out.push('\n');
+                    out.extend(iter::repeat(underline).take(if n < 3 { 3 } else { n }));
+                    out.push('\n');
+                    return;
+                }
+
+                out.extend(iter::repeat('#').take(level));
+                if !text.is_empty() {
+                    out.push(' ');
+                    out.push_str(text.as_slice());
+                    // trailing hashes would be taken as a closing sequence
+                    if text.ends_with("#") {
+                        out.pop();
+                        out.push_str("\\#");
+                    }
+                }
+                out.push('\n');
+            }
+
+            BlockQuote(ref content) => {
+                let inner = self.render_document(content, narrow(width, 2), false);
+                for line in inner.lines() {
+                    if line.is_empty() {
+                        out.push_str(">\n");
+                    } else {
+                        out.push_str("> ");
+                        out.push_str(line);
+                        out.push('\n');
+                    }
+                }
+            }
+
+            BlockCode { ref tag, ref content } => {
+                let fence: String = iter::repeat('`').take(max_run(content, '`') + 1).collect();
+                let fence = if fence.len() < 3 { "```".to_string() } else { fence };
+                out.push_str(fence.as_slice());
+                if let Some(ref tag) = *tag {
+                    out.push_str(&**tag);
+                }
+                out.push('\n');
+                out.push_str(&**content);
+                if !content.is_empty() && !content.ends_with("\n") {
+                    out.push('\n');
+                }
+                out.push_str(fence.as_slice());
+                out.push('\n');
+            }
+
+            HtmlBlock(ref s) => {
+                out.push_str(&**s);
+                if !s.ends_with("\n") {
+                    out.push('\n');
+                }
+            }
+
+            OrderedList { start_index, tight, ref items } => {
+                let delim = if alternate { ')' } else { '.' };
+                for (i, item) in items.iter().enumerate() {
+                    // items of a loose list are separated by blank lines
+                    if i > 0 && !tight {
+                        out.push('\n');
+                    }
+                    let n = match self.style.numbering {
+                        ListNumbering::Incrementing => start_index + i,
+                        ListNumbering::AllOnes => start_index
+                    };
+                    let marker = format!("{}{} ", n, delim);
+                    self.render_item(marker.as_slice(), item, width, tight, out);
+                }
+            }
+
+            UnorderedList { tight, ref items } => {
+                let bullet = match (alternate, self.style.bullet) {
+                    (false, c) => c,
+                    (true, '-') => '*',
+                    (true, _) => '-'
+                };
+                let marker = format!("{} ", bullet);
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 && !tight {
+                        out.push('\n');
+                    }
+                    self.render_item(marker.as_slice(), item, width, tight, out);
+                }
+            }
+
+            Paragraph(ref content) => {
+                let mut pieces = Pieces::new();
+                self.render_text(content, &mut pieces);
+                out.push_str(pieces.layout(width).as_slice());
+                out.push('\n');
+            }
+
+            HorizontalRule => out.push_str("---\n")
+        }
+    }
+
+    fn render_item<'a>(&mut self, marker: &str, item: &Document<'a>, width: usize, tight: bool, out: &mut String) {
+        let inner = self.render_document(item, narrow(width, marker.len()), tight);
+        let indent: String = iter::repeat(' ').take(marker.len()).collect();
+
+        let mut first = true;
+        for line in inner.lines() {
+            if first {
+                out.push_str(if line.is_empty() { marker.trim_right() } else { marker });
+                first = false;
+            } else if !line.is_empty() {
+                out.push_str(indent.as_slice());
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        if first {
+            out.push_str(marker.trim_right());
+            out.push('\n');
+        }
+    }
+
+    fn render_text<'a>(&mut self, text: &Text<'a>, out: &mut Pieces) {
+        for inline in text.iter() {
+            match *inline {
+                LineBreak => out.push(Piece::HardBreak),
+
+                Chunk(ref s) => out.push_chunk(&**s),
+
+                Emphasis(ref content) => {
+                    out.push_char(self.style.emphasis);
+                    self.render_text(content, out);
+                    out.push_char(self.style.emphasis);
+                }
+
+                MoreEmphasis(ref content) => {
+                    out.push_char(self.style.emphasis);
+                    out.push_char(self.style.emphasis);
+                    self.render_text(content, out);
+                    out.push_char(self.style.emphasis);
+                    out.push_char(self.style.emphasis);
+                }
+
+                Code(ref s) => out.push_str(code_span(&**s).as_slice()),
+
+                HtmlInline(ref s) => out.push_str(&**s),
+
+                Link { text: None, ref link, .. } => {
+                    out.push_char('<');
+                    out.push_str(link.as_ref().map(|l| &**l).unwrap_or(""));
+                    out.push_char('>');
+                }
+
+                Link { text: Some(ref text), ref link, ref title, ref id } =>
+                    self.render_link("[", text, link, title, id, out),
+
+                Image { ref alt, ref link, ref title, ref id } =>
+                    self.render_link("![", alt, link, title, id, out)
+            }
+        }
+    }
+
+    fn render_link<'a>(&mut self, open: &str, text: &Text<'a>, link: &Option<Cow<'a, str>>,
+                       title: &Option<Cow<'a, str>>, id: &Option<Cow<'a, str>>, out: &mut Pieces) {
+        out.push_str(open);
+        self.render_text(text, out);
+        out.push_char(']');
+
+        let text_label = text_content(text);
+
+        // keep reference links as references if their definition will be rendered
+        if self.style.link_style != LinkStyle::Inline {
+            if let Some(ref id) = *id {
+                let label = if id.trim().is_empty() { text_label.clone() } else { id.to_string() };
+                if self.link_map.contains_key(&normalize_label(label.as_slice())) {
+                    if id.is_empty() {
+                        out.push_str("[]");
+                    } else if &**id != text_label.as_slice() {
+                        out.push_char('[');
+                        out.push_str(&**id);
+                        out.push_char(']');
+                    }
+                    return;
+                }
+            }
+        }
+
+        let link = link.as_ref().map(|l| &**l).unwrap_or("");
+        let title = title.as_ref().map(|t| &**t);
+
+        if self.style.link_style == LinkStyle::Reference {
+            let label = self.generate_label(text_label.as_slice(), link, title);
+            if label != text_label {
+                out.push_char('[');
+                out.push_str(label.as_slice());
+                out.push_char(']');
+            }
+            return;
+        }
+
+        out.push_char('(');
+        let mut dest = String::new();
+        push_destination(&mut dest, link);
+        if let Some(title) = title {
+            dest.push(' ');
+            push_title(&mut dest, title);
+        }
+        out.push_str(dest.as_slice());
+        out.push_char(')');
+    }
+
+    // finds or creates a definition for the link, preferring the link text as a label
+    fn generate_label(&mut self, text: &str, link: &str, title: Option<&str>) -> String {
+        let base: String = text.chars().filter(|&c| c != '[' && c != ']' && c != '\\').collect();
+        let base = if base.trim().is_empty() { "link".to_string() } else { base.trim().to_string() };
+
+        let mut n = 0usize;
+        loop {
+            let label = if n == 0 { base.clone() } else { format!("{} {}", base, n) };
+            let key = normalize_label(label.as_slice());
+
+            let same = self.link_map.get(&key).or_else(|| self.generated.get(&key)).map(|ld|
+                ld.link.as_slice() == link && ld.title.as_ref().map(|t| t.as_slice()) == title
+            );
+            match same {
+                Some(true) => return label,
+                Some(false) => n += 1,
+                None => {
+                    self.generated.insert(key, LinkDescription {
+                        id: label.clone(),
+                        link: link.to_string(),
+                        title: title.map(|t| t.to_string())
+                    });
+                    return label;
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn narrow(width: usize, n: usize) -> usize {
+    if width == 0 { 0 } else if width > n { width - n } else { 1 }
+}
+
+enum Piece {
+    Word(String),
+    Space,
+    SoftBreak,
+    HardBreak
+}
+
+// rendered inline content, split at the places where lines may be wrapped
+struct Pieces {
+    pieces: Vec<Piece>
+}
+
+impl Pieces {
+    fn new() -> Pieces {
+        Pieces { pieces: Vec::new() }
+    }
+
+    #[inline]
+    fn push(&mut self, piece: Piece) {
+        self.pieces.push(piece);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        if let Some(&mut Piece::Word(ref mut w)) = self.pieces.last_mut() {
+            w.push_str(s);
+            return;
+        }
+        self.pieces.push(Piece::Word(s.to_string()));
+    }
+
+    #[inline]
+    fn push_char(&mut self, c: char) {
+        let mut s = String::new();
+        s.push(c);
+        self.push_str(s.as_slice());
+    }
+
+    fn push_chunk(&mut self, s: &str) {
+        let mut word = String::new();
+        for c in s.chars() {
+            match c {
+                ' ' | '\n' => {
+                    if !word.is_empty() {
+                        self.push_str(word.as_slice());
+                        word.clear();
+                    }
+                    self.push(if c == ' ' { Piece::Space } else { Piece::SoftBreak });
+                }
+                '\\' | '*' | '_' | '`' | '[' | ']' => {
+                    word.push('\\');
+                    word.push(c);
+                }
+                c => word.push(c)
+            }
+        }
+        if !word.is_empty() {
+            self.push_str(word.as_slice());
+        }
+    }
+
+    // joins the pieces into lines no longer than width where possible;
+    // width 0 keeps spaces and line breaks as they are
+    fn layout(&self, width: usize) -> String {
+        let mut result = String::new();
+        let mut line_len = 0;
+        let mut line_start = true;
+        let mut pending_space = false;
+
+        for piece in self.pieces.iter() {
+            match *piece {
+                Piece::Word(ref w) => {
+                    let n = w.chars().count();
+                    if pending_space && width > 0 && line_len + 1 + n > width {
+                        result.push('\n');
+                        line_len = 0;
+                        line_start = true;
+                    } else if pending_space {
+                        result.push(' ');
+                        line_len += 1;
+                    }
+                    pending_space = false;
+
+                    if line_start {
+                        push_line_start(&mut result, w.as_slice());
+                        line_start = false;
+                    } else {
+                        result.push_str(w.as_slice());
+                    }
+                    line_len += n;
+                }
+
+                Piece::Space if width == 0 => { result.push(' '); line_len += 1; }
+                Piece::SoftBreak if width == 0 => { result.push('\n'); line_len = 0; line_start = true; }
+                Piece::Space | Piece::SoftBreak => pending_space = line_len > 0,
+
+                Piece::HardBreak => {
+                    result.push_str("\\\n");
+                    line_len = 0;
+                    line_start = true;
+                    pending_space = false;
+                }
+            }
+        }
+
+        result
+    }
+}
+
+fn same_list_kind(a: &Block, b: &Block) -> bool {
+    match (a, b) {
+        (&OrderedList { .. }, &OrderedList { .. }) |
+        (&UnorderedList { .. }, &UnorderedList { .. }) => true,
+        _ => false
+    }
+}
+
+// escapes the beginning of a word which starts a line if it could be taken
+// for a block marker
+fn push_line_start(out: &mut String, w: &str) {
+    match w.chars().next() {
+        Some('#') | Some('>') | Some('+') | Some('-') | Some('~') | Some('=') => {
+            out.push('\\');
+            out.push_str(w);
+        }
+        Some(c) if c.is_digit(10) => {
+            let digits = w.chars().take_while(|c| c.is_digit(10)).count();
+            match w[digits..].chars().next() {
+                Some('.') | Some(')') => {
+                    out.push_str(&w[..digits]);
+                    out.push('\\');
+                    out.push_str(&w[digits..]);
+                }
+                _ => out.push_str(w)
+            }
+        }
+        _ => out.push_str(w)
+    }
+}
+
+fn code_span(s: &str) -> String {
+    let fence: String = iter::repeat('`').take(max_run(s, '`') + 1).collect();
+    let pad = if s.starts_with("`") || s.ends_with("`") { " " } else { "" };
+    format!("{}{}{}{}{}", fence, pad, s, pad, fence)
+}
+
+fn push_destination(out: &mut String, link: &str) {
+    let mut level = 0i32;
+    let mut balanced = true;
+    for c in link.chars() {
+        match c {
+            '(' => level += 1,
+            ')' => { level -= 1; if level < 0 { balanced = false; } }
+            _ => {}
+        }
+    }
+    if link.is_empty() || !balanced || level != 0 || link.contains(" ") {
+        out.push('<');
+        out.push_str(link);
+        out.push('>');
+    } else {
+        out.push_str(link);
+    }
+}
+
+fn push_title(out: &mut String, title: &str) {
+    out.push('"');
+    for c in title.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+// the length of the longest run of the character in the string
+fn max_run(s: &str, c: char) -> usize {
+    let mut result = 0;
+    let mut n = 0;
+    for sc in s.chars() {
+        if sc == c {
+            n += 1;
+            if n > result { result = n; }
+        } else {
+            n = 0;
+        }
+    }
+    result
+}
@@ -0,0 +1,8 @@
+//! Renderers of the token tree to various output formats.
+
+pub mod markdown;
+pub mod ansi;
+pub mod latex;
+pub mod plain;
+pub mod roff;
+pub mod xml;
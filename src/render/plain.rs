@@ -0,0 +1,160 @@
+//! Plain text renderer.
+//!
+//! Produces only the readable text of a document, e.g. for search indexing
+//! or previews. Link and image destinations are dropped while their text is
+//! kept, list items are prefixed with `-` or their number and block quotes
+//! with `>`. Line breaks inside paragraphs are replaced with spaces, hard
+//! breaks are kept as newlines.
+
+use std::iter;
+
+use tokens::*;
+
+#[derive(Copy, Clone)]
+pub struct PlainStyle {
+    /// Whether contents of code blocks are included; inline code is always kept.
+    pub code_blocks: bool,
+    /// The maximum number of characters in the output, 0 means no limit. Longer
+    /// output is cut at a word boundary and ends with `…`.
+    pub limit: usize
+}
+
+impl PlainStyle {
+    #[inline]
+    pub fn default() -> PlainStyle {
+        PlainStyle {
+            code_blocks: true,
+            limit: 0
+        }
+    }
+}
+
+impl_setters! { PlainStyle;
+    code_blocks: bool,
+    limit: usize
+}
+
+#[inline]
+pub fn to_plain_text<'a>(doc: &Document<'a>) -> String {
+    to_plain_text_with_style(doc, PlainStyle::default())
+}
+
+pub fn to_plain_text_with_style<'a>(doc: &Document<'a>, style: PlainStyle) -> String {
+    let result = render_document(doc, style);
+    if style.limit > 0 {
+        truncate(result, style.limit)
+    } else {
+        result
+    }
+}
+
+// blocks are separated by empty lines; blocks without text are skipped
+fn render_document<'a>(doc: &Document<'a>, style: PlainStyle) -> String {
+    let mut result = String::new();
+    for block in doc.iter() {
+        let s = render_block(block, style);
+        if s.is_empty() { continue }
+        if !result.is_empty() {
+            result.push_str("\n\n");
+        }
+        result.push_str(s.as_slice());
+    }
+    result
+}
+
+fn render_block<'a>(block: &Block<'a>, style: PlainStyle) -> String {
+    match *block {
+        Heading { ref content, .. } | Paragraph(ref content) => render_text(content),
+
+        BlockQuote(ref content) => {
+            let inner = render_document(content, style);
+            let lines: Vec<String> = inner.lines()
+                .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+                .collect();
+            lines.connect("\n")
+        }
+
+        BlockCode { ref content, .. } if style.code_blocks =>
+            content.trim_right_matches('\n').to_string(),
+        BlockCode { .. } => String::new(),
+
+        HtmlBlock(_) => String::new(),
+
+        OrderedList { start_index, ref items, .. } => {
+            let items: Vec<String> = items.iter().enumerate()
+                .map(|(i, item)| render_item(format!("{}. ", start_index + i).as_slice(), item, style))
+                .collect();
+            items.connect("\n")
+        }
+
+        UnorderedList { ref items, .. } => {
+            let items: Vec<String> = items.iter()
+                .map(|item| render_item("- ", item, style))
+                .collect();
+            items.connect("\n")
+        }
+
+        HorizontalRule => String::new()
+    }
+}
+
+fn render_item<'a>(marker: &str, item: &Document<'a>, style: PlainStyle) -> String {
+    let inner = render_document(item, style);
+    let indent: String = iter::repeat(' ').take(marker.len()).collect();
+
+    let mut result = marker.trim_right().to_string();
+    for (i, line) in inner.lines().enumerate() {
+        if i == 0 {
+            result.push(' ');
+        } else {
+            result.push('\n');
+            if !line.is_empty() {
+                result.push_str(indent.as_slice());
+            }
+        }
+        result.push_str(line);
+    }
+    result
+}
+
+fn render_text<'a>(text: &Text<'a>) -> String {
+    let mut result = String::new();
+    push_text(text, &mut result);
+    result.trim().to_string()
+}
+
+fn push_text<'a>(text: &Text<'a>, out: &mut String) {
+    for inline in text.iter() {
+        match *inline {
+            LineBreak => out.push('\n'),
+            Chunk(ref s) => out.extend(s.chars().map(|c| if c == '\n' { ' ' } else { c })),
+            Emphasis(ref content) | MoreEmphasis(ref content) => push_text(content, out),
+            Code(ref s) => out.push_str(&**s),
+            HtmlInline(_) => {}
+            Link { text: Some(ref content), .. } => push_text(content, out),
+            Link { text: None, ref link, .. } => out.push_str(link.as_ref().map(|l| &**l).unwrap_or("")),
+            Image { ref alt, .. } => push_text(alt, out)
+        }
+    }
+}
+
+// cuts the text to at most limit characters, at the last whitespace if there
+// is one, and marks the cut with an ellipsis
+fn truncate(s: String, limit: usize) -> String {
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    if chars.len() <= limit { return s }
+
+    let cut = limit - 1;  // room for the ellipsis
+    let end = if chars[cut].1.is_whitespace() {
+        chars[cut].0
+    } else {
+        match chars[..cut].iter().rposition(|&(_, c)| c.is_whitespace()) {
+            Some(i) => chars[i].0,
+            None => chars[cut].0  // a single long word
+        }
+    };
+
+    let mut result = s[..end].trim_right().to_string();
+    result.push('…');
+    result
+}
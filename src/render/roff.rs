@@ -0,0 +1,260 @@
+//! roff renderer.
+//!
+//! Produces manual pages using `man(7)` macros: headings of levels 1 and 2
+//! become `.SH` and `.SS` sections, deeper ones bold paragraphs; code blocks
+//! are set in no-fill mode, lists use `.IP` and `.TP` and block quotes are
+//! indented with `.RS`. Emphasis is rendered with font escapes, since `.I` and
+//! `.B` macros can't be used in the middle of a line. Link destinations are
+//! written after the link text.
+
+use tokens::*;
+
+#[derive(Clone)]
+pub struct RoffStyle {
+    /// When set, the page starts with a `.TH` title line with this title.
+    pub title: Option<String>,
+    /// The manual section written to the title line.
+    pub section: String
+}
+
+impl RoffStyle {
+    #[inline]
+    pub fn default() -> RoffStyle {
+        RoffStyle {
+            title: None,
+            section: "1".to_string()
+        }
+    }
+}
+
+impl_setters! { RoffStyle;
+    title: Option<String>,
+    section: String
+}
+
+#[inline]
+pub fn to_roff<'a>(doc: &Document<'a>) -> String {
+    to_roff_with_style(doc, RoffStyle::default())
+}
+
+pub fn to_roff_with_style<'a>(doc: &Document<'a>, style: RoffStyle) -> String {
+    let mut r = Renderer {
+        out: String::new(),
+        line: String::new(),
+        bold: 0,
+        italic: 0
+    };
+    if let Some(ref title) = style.title {
+        r.request(format!(".TH {} {}", quote(title.as_slice()), quote(style.section.as_slice())).as_slice());
+    }
+    r.render_document(doc, false);
+    r.end_line();
+    r.out
+}
+
+struct Renderer {
+    out: String,
+    // the current text line
+    line: String,
+    bold: usize,
+    italic: usize
+}
+
+impl Renderer {
+    // inside list items paragraphs continue the item indentation, and the
+    // first block follows the item tag directly
+    fn render_document<'a>(&mut self, doc: &Document<'a>, in_item: bool) {
+        for (i, block) in doc.iter().enumerate() {
+            self.render_block(block, in_item, in_item && i == 0);
+        }
+    }
+
+    fn render_block<'a>(&mut self, block: &Block<'a>, in_item: bool, first_in_item: bool) {
+        match *block {
+            Heading { level, ref content } if level <= 2 => {
+                self.request(if level == 1 { ".SH" } else { ".SS" });
+                self.render_text(content);
+                self.end_line();
+            }
+
+            Heading { ref content, .. } => {
+                self.paragraph(in_item, first_in_item);
+                self.set_font(1, 0);
+                self.render_text(content);
+                self.set_font(-1, 0);
+                self.end_line();
+            }
+
+            BlockQuote(ref content) => {
+                self.request(".RS 4");
+                self.render_document(content, false);
+                self.request(".RE");
+            }
+
+            BlockCode { ref content, .. } => {
+                if in_item {
+                    self.paragraph(in_item, first_in_item);
+                } else {
+                    self.request(".PP");
+                    self.request(".RS 4");
+                }
+                self.request(".nf");
+                for line in content.trim_right_matches('\n').split('\n') {
+                    let mut escaped = String::new();
+                    for (i, c) in line.chars().enumerate() {
+                        push_escaped(&mut escaped, c, i == 0);
+                    }
+                    self.request(escaped.as_slice());
+                }
+                self.request(".fi");
+                if !in_item {
+                    self.request(".RE");
+                }
+            }
+
+            HtmlBlock(_) => {}
+
+            OrderedList { start_index, ref items, .. } => {
+                if in_item { self.request(".RS"); }
+                for (i, item) in items.iter().enumerate() {
+                    self.request(".TP 4");
+                    self.request(format!("{}.", start_index + i).as_slice());
+                    self.render_document(item, true);
+                }
+                if in_item { self.request(".RE"); }
+            }
+
+            UnorderedList { ref items, .. } => {
+                if in_item { self.request(".RS"); }
+                for item in items.iter() {
+                    self.request(".IP \\(bu 2");
+                    self.render_document(item, true);
+                }
+                if in_item { self.request(".RE"); }
+            }
+
+            Paragraph(ref content) => {
+                self.paragraph(in_item, first_in_item);
+                self.render_text(content);
+                self.end_line();
+            }
+
+            HorizontalRule => {
+                self.request(".PP");
+                self.request("\\l'\\n(.lu'");
+            }
+        }
+    }
+
+    fn paragraph(&mut self, in_item: bool, first_in_item: bool) {
+        if first_in_item {
+            return;
+        }
+        self.request(if in_item { ".IP" } else { ".PP" });
+    }
+
+    fn render_text<'a>(&mut self, text: &Text<'a>) {
+        for inline in text.iter() {
+            match *inline {
+                LineBreak => self.request(".br"),
+
+                Chunk(ref s) => self.push_text(&**s),
+
+                Emphasis(ref content) => {
+                    self.set_font(0, 1);
+                    self.render_text(content);
+                    self.set_font(0, -1);
+                }
+
+                MoreEmphasis(ref content) => {
+                    self.set_font(1, 0);
+                    self.render_text(content);
+                    self.set_font(-1, 0);
+                }
+
+                Code(ref s) => {
+                    self.line.push_str("\\f(CW");
+                    self.push_text(&**s);
+                    self.set_font(0, 0);
+                }
+
+                HtmlInline(_) => {}
+
+                Link { text: None, ref link, .. } =>
+                    self.push_text(link.as_ref().map(|l| &**l).unwrap_or("")),
+
+                Link { text: Some(ref text), ref link, .. } => {
+                    self.render_text(text);
+                    if let Some(ref link) = *link {
+                        if text_content(text).as_slice() != &**link {
+                            self.push_text(format!(" <{}>", link).as_slice());
+                        }
+                    }
+                }
+
+                Image { ref alt, .. } => self.render_text(alt)
+            }
+        }
+    }
+
+    // changes font nesting levels and switches to the resulting font
+    fn set_font(&mut self, bold: isize, italic: isize) {
+        self.bold = (self.bold as isize + bold) as usize;
+        self.italic = (self.italic as isize + italic) as usize;
+        self.line.push_str(match (self.bold > 0, self.italic > 0) {
+            (true, true) => "\\f(BI",
+            (true, false) => "\\fB",
+            (false, true) => "\\fI",
+            (false, false) => "\\fR"
+        });
+    }
+
+    fn push_text(&mut self, s: &str) {
+        for c in s.chars() {
+            match c {
+                '\n' => self.end_line(),
+                ' ' if self.line.is_empty() => {}
+                c => {
+                    let line_start = self.line.is_empty();
+                    push_escaped(&mut self.line, c, line_start);
+                }
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.trim().is_empty() {
+            self.out.push_str(self.line.trim_right());
+            self.out.push('\n');
+        }
+        self.line.clear();
+    }
+
+    // writes a line as it is, after the current text line
+    fn request(&mut self, s: &str) {
+        self.end_line();
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+}
+
+fn push_escaped(out: &mut String, c: char, line_start: bool) {
+    match c {
+        '.' | '\'' if line_start => { out.push_str("\\&"); out.push(c); }
+        '\\' => out.push_str("\\e"),
+        '-' => out.push_str("\\-"),
+        c => out.push(c)
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut result = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\"\""),
+            c => push_escaped(&mut result, c, false)
+        }
+    }
+    result.push('"');
+    result
+}
@@ -0,0 +1,257 @@
+//! CommonMark XML renderer.
+//!
+//! Produces the XML representation of the syntax tree described by
+//! `CommonMark.dtd`, as emitted by the CommonMark reference implementation.
+//! Tokens do not carry their source spans; `to_xml_with_sourcepos()` writes
+//! `sourcepos` attributes of blocks and list items from the spans returned
+//! by `MarkdownParser::read_all_with_spans()`. Inline elements get none.
+
+use std::iter;
+use std::slice;
+
+use tokens::*;
+use parser::Span;
+
+#[inline]
+pub fn to_xml<'a>(doc: &Document<'a>) -> String {
+    to_xml_with_sourcepos(doc, &[])
+}
+
+/// Renders the document with `sourcepos` attributes. `spans` are spans of the
+/// blocks and list items of the document in the order they start; blocks
+/// past the end of them get no attribute.
+pub fn to_xml_with_sourcepos<'a>(doc: &Document<'a>, spans: &[Span]) -> String {
+    let mut r = Renderer { out: String::new(), depth: 0, spans: spans.iter() };
+    r.out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    r.out.push_str("<!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n");
+    r.open("document xmlns=\"http://commonmark.org/xml/1.0\"");
+    r.render_document(doc);
+    r.close("document");
+    r.out
+}
+
+struct Renderer<'s> {
+    out: String,
+    depth: usize,
+    spans: slice::Iter<'s, Span>
+}
+
+impl<'s> Renderer<'s> {
+    fn render_document<'a>(&mut self, doc: &Document<'a>) {
+        for block in doc.iter() {
+            self.render_block(block);
+        }
+    }
+
+    fn render_block<'a>(&mut self, block: &Block<'a>) {
+        match *block {
+            Heading { level, ref content } => {
+                let pos = self.sourcepos();
+                self.open(format!("heading level=\"{}\"{}", level, pos).as_slice());
+                self.render_text(content);
+                self.close("heading");
+            }
+
+            BlockQuote(ref content) => {
+                let pos = self.sourcepos();
+                self.open(format!("block_quote{}", pos).as_slice());
+                self.render_document(content);
+                self.close("block_quote");
+            }
+
+            BlockCode { ref tag, ref content } => {
+                let pos = self.sourcepos();
+                self.indent();
+                self.out.push_str("<code_block");
+                self.out.push_str(pos.as_slice());
+                if let Some(ref tag) = *tag {
+                    self.out.push_str(" info=\"");
+                    push_escaped(&mut self.out, &**tag);
+                    self.out.push('"');
+                }
+                self.out.push_str(" xml:space=\"preserve\">");
+                push_escaped(&mut self.out, &**content);
+                self.out.push_str("</code_block>\n");
+            }
+
+            HtmlBlock(ref s) => {
+                let pos = self.sourcepos();
+                self.leaf_with_attrs("html_block", pos.as_slice(), &**s);
+            }
+
+            OrderedList { start_index, tight, ref items } => {
+                let pos = self.sourcepos();
+                self.open(format!("list type=\"ordered\" start=\"{}\" delim=\"period\" tight=\"{}\"{}",
+                                  start_index, tight, pos).as_slice());
+                self.render_items(items);
+                self.close("list");
+            }
+
+            UnorderedList { tight, ref items } => {
+                let pos = self.sourcepos();
+                self.open(format!("list type=\"bullet\" tight=\"{}\"{}", tight, pos).as_slice());
+                self.render_items(items);
+                self.close("list");
+            }
+
+            Paragraph(ref content) => {
+                let pos = self.sourcepos();
+                self.open(format!("paragraph{}", pos).as_slice());
+                self.render_text(content);
+                self.close("paragraph");
+            }
+
+            HorizontalRule => {
+                let pos = self.sourcepos();
+                self.empty(format!("thematic_break{}", pos).as_slice());
+            }
+        }
+    }
+
+    fn render_items<'a>(&mut self, items: &Vec<Document<'a>>) {
+        for item in items.iter() {
+            let pos = self.sourcepos();
+            if item.is_empty() {
+                self.empty(format!("item{}", pos).as_slice());
+            } else {
+                self.open(format!("item{}", pos).as_slice());
+                self.render_document(item);
+                self.close("item");
+            }
+        }
+    }
+
+    fn render_text<'a>(&mut self, text: &Text<'a>) {
+        for inline in text.iter() {
+            match *inline {
+                LineBreak => self.empty("linebreak"),
+
+                // soft line breaks are separate nodes
+                Chunk(ref s) =>
+                    for (i, line) in s.split('\n').enumerate() {
+                        if i > 0 {
+                            self.empty("softbreak");
+                        }
+                        if !line.is_empty() {
+                            self.leaf("text", line);
+                        }
+                    },
+
+                Emphasis(ref content) => {
+                    self.open("emph");
+                    self.render_text(content);
+                    self.close("emph");
+                }
+
+                MoreEmphasis(ref content) => {
+                    self.open("strong");
+                    self.render_text(content);
+                    self.close("strong");
+                }
+
+                Code(ref s) => self.leaf("code", &**s),
+
+                HtmlInline(ref s) => self.leaf("html_inline", &**s),
+
+                Link { text: None, ref link, ref title, .. } => {
+                    let link = link.as_ref().map(|l| &**l).unwrap_or("");
+                    self.open(link_tag("link", link, title.as_ref().map(|t| &**t)).as_slice());
+                    self.leaf("text", link);
+                    self.close("link");
+                }
+
+                Link { text: Some(ref text), ref link, ref title, .. } => {
+                    let link = link.as_ref().map(|l| &**l).unwrap_or("");
+                    self.open(link_tag("link", link, title.as_ref().map(|t| &**t)).as_slice());
+                    self.render_text(text);
+                    self.close("link");
+                }
+
+                Image { ref alt, ref link, ref title, .. } => {
+                    let link = link.as_ref().map(|l| &**l).unwrap_or("");
+                    self.open(link_tag("image", link, title.as_ref().map(|t| &**t)).as_slice());
+                    self.render_text(alt);
+                    self.close("image");
+                }
+            }
+        }
+    }
+
+    // the attribute with the span of the next block, like "1:1-2:5" with both
+    // ends inclusive
+    fn sourcepos(&mut self) -> String {
+        match self.spans.next() {
+            Some(span) => format!(" sourcepos=\"{}:{}-{}:{}\"", span.start.line, span.start.column,
+                                  span.end.line, span.end.column - 1),
+            None => String::new()
+        }
+    }
+
+    fn indent(&mut self) {
+        self.out.extend(iter::repeat(' ').take(self.depth * 2));
+    }
+
+    // tag includes the attributes
+    fn open(&mut self, tag: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.out.push_str(">\n");
+        self.depth += 1;
+    }
+
+    fn close(&mut self, name: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.out.push_str("</");
+        self.out.push_str(name);
+        self.out.push_str(">\n");
+    }
+
+    // tag includes the attributes
+    fn empty(&mut self, tag: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.out.push_str(" />\n");
+    }
+
+    #[inline]
+    fn leaf(&mut self, name: &str, content: &str) {
+        self.leaf_with_attrs(name, "", content);
+    }
+
+    fn leaf_with_attrs(&mut self, name: &str, attrs: &str, content: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(name);
+        self.out.push_str(attrs);
+        self.out.push_str(" xml:space=\"preserve\">");
+        push_escaped(&mut self.out, content);
+        self.out.push_str("</");
+        self.out.push_str(name);
+        self.out.push_str(">\n");
+    }
+}
+
+fn link_tag(name: &str, link: &str, title: Option<&str>) -> String {
+    let mut result = name.to_string();
+    result.push_str(" destination=\"");
+    push_escaped(&mut result, link);
+    result.push_str("\" title=\"");
+    push_escaped(&mut result, title.unwrap_or(""));
+    result.push('"');
+    result
+}
+
+fn push_escaped(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c)
+        }
+    }
+}
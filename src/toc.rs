@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+
+use tokens::*;
+
+/// A heading found in a document, flattened.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HeadingInfo {
+    pub level: usize,
+    pub title: String,
+    pub anchor: String
+}
+
+/// A single table of contents entry with its nested subentries.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TocEntry {
+    pub level: usize,
+    pub title: String,
+    pub anchor: String,
+    pub children: Vec<TocEntry>
+}
+
+pub type Toc = Vec<TocEntry>;
+
+/// Collects all headings of the document in document order, descending into
+/// block quotes and list items.
+pub fn collect_headings<'a>(doc: &Document<'a>) -> Vec<HeadingInfo> {
+    let mut anchors = Anchors::new();
+    let mut result = Vec::new();
+    collect_into(doc, &mut anchors, &mut result);
+    result
+}
+
+fn collect_into<'a>(doc: &Document<'a>, anchors: &mut Anchors, result: &mut Vec<HeadingInfo>) {
+    for block in doc.iter() {
+        match *block {
+            Heading { level, ref content } => {
+                let title = text_content(content);
+                let anchor = anchors.generate(&title);
+                result.push(HeadingInfo {
+                    level: level,
+                    title: title,
+                    anchor: anchor
+                });
+            }
+
+            BlockQuote(ref content) => collect_into(content, anchors, result),
+
+            OrderedList { ref items, .. } | UnorderedList { ref items, .. } =>
+                for item in items.iter() {
+                    collect_into(item, anchors, result);
+                },
+
+            _ => {}
+        }
+    }
+}
+
+/// Builds a nested table of contents for the document.
+///
+/// Each heading becomes a child of the closest preceding heading with a smaller
+/// level, so skipped levels (e.g. `#` followed by `###`) still nest properly.
+pub fn build_toc<'a>(doc: &Document<'a>) -> Toc {
+    nest(collect_headings(doc))
+}
+
+fn nest(headings: Vec<HeadingInfo>) -> Toc {
+    // stack of currently open entries, each one is a child of the previous one
+    let mut stack: Vec<TocEntry> = Vec::new();
+    let mut result = Vec::new();
+
+    for h in headings.into_iter() {
+        while stack.last().map(|e| e.level >= h.level).unwrap_or(false) {
+            close_last(&mut stack, &mut result);
+        }
+        stack.push(TocEntry {
+            level: h.level,
+            title: h.title,
+            anchor: h.anchor,
+            children: Vec::new()
+        });
+    }
+
+    while !stack.is_empty() {
+        close_last(&mut stack, &mut result);
+    }
+
+    result
+}
+
+fn close_last(stack: &mut Vec<TocEntry>, result: &mut Toc) {
+    let entry = stack.pop().unwrap();
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(entry),
+        None => result.push(entry)
+    }
+}
+
+/// Renders the table of contents as an unordered list of links to anchors.
+#[inline]
+pub fn to_unordered_list(toc: &[TocEntry]) -> Block<'static> {
+    to_list(toc, false)
+}
+
+/// Renders the table of contents as an ordered list of links to anchors.
+#[inline]
+pub fn to_ordered_list(toc: &[TocEntry]) -> Block<'static> {
+    to_list(toc, true)
+}
+
+fn to_list(toc: &[TocEntry], ordered: bool) -> Block<'static> {
+    let items = toc.iter().map(|e| {
+        let mut item = vec![Paragraph(vec![Link {
+            text: Some(vec![Chunk(Cow::Owned(e.title.clone()))]),
+            link: Some(Cow::Owned(format!("#{}", e.anchor))),
+            title: None,
+            id: None
+        }])];
+        if !e.children.is_empty() {
+            item.push(to_list(e.children.as_slice(), ordered));
+        }
+        item
+    }).collect();
+
+    if ordered {
+        OrderedList { start_index: 1, tight: true, items: items }
+    } else {
+        UnorderedList { tight: true, items: items }
+    }
+}
+
+/// Replaces every `[TOC]` placeholder paragraph in the document with a copy of
+/// the given block. Returns the number of replaced placeholders.
+pub fn replace_placeholder<'a>(doc: &mut Document<'a>, toc: &Block<'a>) -> usize {
+    let mut n = 0;
+    for block in doc.iter_mut() {
+        if is_placeholder(block) {
+            *block = toc.clone();
+            n += 1;
+            continue;
+        }
+
+        match *block {
+            BlockQuote(ref mut content) => n += replace_placeholder(content, toc),
+
+            OrderedList { ref mut items, .. } | UnorderedList { ref mut items, .. } =>
+                for item in items.iter_mut() {
+                    n += replace_placeholder(item, toc);
+                },
+
+            _ => {}
+        }
+    }
+    n
+}
+
+fn is_placeholder(block: &Block) -> bool {
+    match *block {
+        Paragraph(ref content) if content.len() == 1 => match content[0] {
+            Chunk(ref s) => s.trim() == "[TOC]",
+            // `[TOC]` is parsed as a shortcut reference link which is left unresolved
+            Link { link: None, id: Some(ref id), .. } => &**id == "TOC",
+            _ => false
+        },
+        _ => false
+    }
+}
+
+/// Generates unique anchor identifiers for headings.
+struct Anchors {
+    // how many headings had each slug
+    used: HashMap<String, usize>,
+    // every anchor generated so far, numbered ones included
+    issued: HashSet<String>
+}
+
+impl Anchors {
+    fn new() -> Anchors {
+        Anchors { used: HashMap::new(), issued: HashSet::new() }
+    }
+
+    fn generate(&mut self, title: &str) -> String {
+        let base = slugify(title);
+        let mut n = self.used.get(&base).map(|&n| n).unwrap_or(0);
+        let mut anchor = if n == 0 { base.clone() } else { format!("{}-{}", base, n) };
+        // a numbered anchor may clash with the slug of another heading,
+        // e.g. the second `A` with `A 1`, or the other way round
+        while self.issued.contains(&anchor) {
+            n += 1;
+            anchor = format!("{}-{}", base, n);
+        }
+        self.used.insert(base, n + 1);
+        self.issued.insert(anchor.clone());
+        anchor
+    }
+}
+
+/// Converts a heading title to an anchor: letters and digits are lowercased,
+/// spaces and hyphens become hyphens, everything else is dropped.
+fn slugify(title: &str) -> String {
+    let mut result = String::new();
+    for c in title.trim().chars() {
+        if c.is_alphanumeric() || c == '_' {
+            result.extend(c.to_lowercase());
+        } else if c == ' ' || c == '-' {
+            result.push('-');
+        }
+    }
+    if result.is_empty() {
+        result.push_str("section");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use tokens::*;
+    use parser::MarkdownParser;
+    use super::{slugify, nest, is_placeholder, replace_placeholder, Anchors, HeadingInfo, TocEntry};
+
+    fn heading(level: usize, title: &str) -> HeadingInfo {
+        HeadingInfo { level: level, title: title.to_string(), anchor: title.to_string() }
+    }
+
+    fn entry(level: usize, title: &str, children: Vec<TocEntry>) -> TocEntry {
+        TocEntry { level: level, title: title.to_string(), anchor: title.to_string(), children: children }
+    }
+
+    fn chunk(s: &'static str) -> Inline<'static> {
+        Chunk(Cow::Borrowed(s))
+    }
+
+    #[test]
+    fn slugify_lowercases_and_drops_punctuation() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  snake_case and-dash  "), "snake_case-and-dash");
+        assert_eq!(slugify("Ärger mit ΣΧΟΛΕΙΟ"), "ärger-mit-σχολειο");
+        assert_eq!(slugify("?!"), "section");
+    }
+
+    #[test]
+    fn numbered_anchors_do_not_clash() {
+        let mut anchors = Anchors::new();
+        let generated: Vec<String> = ["A", "A", "A 1", "A"].iter().map(|t| anchors.generate(t)).collect();
+        assert_eq!(generated, vec!["a", "a-1", "a-1-1", "a-2"]);
+
+        let mut anchors = Anchors::new();
+        let generated: Vec<String> = ["A 1", "A", "A"].iter().map(|t| anchors.generate(t)).collect();
+        assert_eq!(generated, vec!["a-1", "a", "a-2"]);
+    }
+
+    #[test]
+    fn nest_with_skipped_levels() {
+        let toc = nest(vec![heading(1, "a"), heading(3, "b"), heading(2, "c"), heading(4, "d"), heading(1, "e")]);
+        assert_eq!(toc, vec![
+            entry(1, "a", vec![
+                entry(3, "b", vec![]),
+                entry(2, "c", vec![entry(4, "d", vec![])])
+            ]),
+            entry(1, "e", vec![])
+        ]);
+    }
+
+    #[test]
+    fn nest_starting_below_top_level() {
+        let toc = nest(vec![heading(3, "a"), heading(1, "b"), heading(2, "c")]);
+        assert_eq!(toc, vec![
+            entry(3, "a", vec![]),
+            entry(1, "b", vec![entry(2, "c", vec![])])
+        ]);
+    }
+
+    #[test]
+    fn placeholder_forms() {
+        assert!(is_placeholder(&Paragraph(vec![chunk(" [TOC] ")])));
+        assert!(is_placeholder(&Paragraph(vec![Link {
+            text: Some(vec![chunk("TOC")]),
+            link: None,
+            title: None,
+            id: Some(Cow::Borrowed("TOC"))
+        }])));
+
+        assert!(!is_placeholder(&Paragraph(vec![chunk("[TOC] here")])));
+        assert!(!is_placeholder(&Paragraph(vec![Link {
+            text: Some(vec![chunk("TOC")]),
+            link: Some(Cow::Borrowed("/toc")),
+            title: None,
+            id: Some(Cow::Borrowed("TOC"))
+        }])));
+        assert!(!is_placeholder(&Paragraph(vec![chunk("[TOC]"), LineBreak])));
+        assert!(!is_placeholder(&Heading { level: 1, content: vec![chunk("[TOC]")] }));
+    }
+
+    #[test]
+    fn parsed_placeholders_are_replaced() {
+        let mut doc = MarkdownParser::new(b"[TOC]\n\n> [TOC]\n\n- [TOC]\n").read_all();
+        let n = replace_placeholder(&mut doc, &HorizontalRule);
+        assert_eq!(n, 3);
+        assert_eq!(doc[0], HorizontalRule);
+    }
+}
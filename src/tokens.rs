@@ -1,72 +1,210 @@
 use std::collections::HashMap;
+use std::borrow::Cow;
+use std::mem;
+
+use visit::{VisitorMut, walk_inline_mut};
 
 pub use self::Block::*;
 pub use self::Inline::*;
 
-pub type Document = Vec<Block>;
+// Tokens borrow from the parsed buffer wherever possible; use `into_owned()`
+// to detach them from it.
+
+pub type Document<'a> = Vec<Block<'a>>;
 
-pub type Text = Vec<Inline>;
+pub type Text<'a> = Vec<Inline<'a>>;
 
 pub type LinkMap = HashMap<String, LinkDescription>;
 
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LinkDescription {
     pub id: String,
     pub link: String,
     pub title: Option<String>
 }
 
+/// A document together with the reference definitions from its source, so
+/// that it can be rendered back with reference links.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParsedDocument<'a> {
+    pub blocks: Document<'a>,
+    pub link_map: LinkMap
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
-pub enum Block {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data", rename_all = "snake_case"))]
+pub enum Block<'a> {
     Heading {
         level: usize,
-        content: Text
+        content: Text<'a>
     },
     
-    BlockQuote(Document),
+    BlockQuote(Document<'a>),
 
     BlockCode {
-        tag: Option<String>,
-        content: String
+        tag: Option<Cow<'a, str>>,
+        content: Cow<'a, str>
     },
 
+    /// Raw HTML, kept as it is.
+    HtmlBlock(Cow<'a, str>),
+
+    /// A list is tight if its items are not separated by blank lines and
+    /// no item contains blocks separated by blank lines.
     OrderedList {
         start_index: usize,
-        items: Vec<Document>
+        #[cfg_attr(feature = "serde", serde(default = "tight_by_default"))]
+        tight: bool,
+        items: Vec<Document<'a>>
     },
 
     UnorderedList {
-        items: Vec<Document>
+        #[cfg_attr(feature = "serde", serde(default = "tight_by_default"))]
+        tight: bool,
+        items: Vec<Document<'a>>
     },
 
-    Paragraph(Text),
+    Paragraph(Text<'a>),
 
     HorizontalRule
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
-pub enum Inline{
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data", rename_all = "snake_case"))]
+pub enum Inline<'a> {
     LineBreak,
 
-    Chunk(String),
+    Chunk(Cow<'a, str>),
+
+    Emphasis(Text<'a>),
 
-    Emphasis(Text),
+    MoreEmphasis(Text<'a>),
 
-    MoreEmphasis(Text),
+    Code(Cow<'a, str>),
 
-    Code(String),
+    /// Raw HTML, kept as it is.
+    HtmlInline(Cow<'a, str>),
 
     Link {
-        text: Option<Text>,  // None for automatic links
-        link: Option<String>,
-        title: Option<String>,
-        id: Option<String>
+        text: Option<Text<'a>>,  // None for automatic links
+        link: Option<Cow<'a, str>>,
+        title: Option<Cow<'a, str>>,
+        id: Option<Cow<'a, str>>
     },
 
     Image {
-        alt: Text,
-        link: Option<String>,
-        title: Option<String>,
-        id: Option<String>
+        alt: Text<'a>,
+        link: Option<Cow<'a, str>>,
+        title: Option<Cow<'a, str>>,
+        id: Option<Cow<'a, str>>
+    }
+}
+
+// lists serialized before tightness was recorded are tight
+#[cfg(feature = "serde")]
+fn tight_by_default() -> bool { true }
+
+pub trait IntoOwned {
+    type Owned;
+
+    fn into_owned(self) -> Self::Owned;
+}
+
+#[inline]
+fn owned_str<'a>(s: Cow<'a, str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
+#[inline]
+fn owned_opt_str<'a>(s: Option<Cow<'a, str>>) -> Option<Cow<'static, str>> {
+    s.map(owned_str)
+}
+
+impl<'a> IntoOwned for ParsedDocument<'a> {
+    type Owned = ParsedDocument<'static>;
+
+    #[inline]
+    fn into_owned(self) -> ParsedDocument<'static> {
+        ParsedDocument {
+            blocks: self.blocks.into_owned(),
+            link_map: self.link_map
+        }
+    }
+}
+
+impl<'a> IntoOwned for Block<'a> {
+    type Owned = Block<'static>;
+
+    fn into_owned(self) -> Block<'static> {
+        match self {
+            Heading { level, content } => Heading { level: level, content: content.into_owned() },
+            BlockQuote(content) => BlockQuote(content.into_owned()),
+            BlockCode { tag, content } => BlockCode {
+                tag: owned_opt_str(tag),
+                content: owned_str(content)
+            },
+            HtmlBlock(s) => HtmlBlock(owned_str(s)),
+            OrderedList { start_index, tight, items } => OrderedList {
+                start_index: start_index,
+                tight: tight,
+                items: items.into_iter().map(|item| item.into_owned()).collect()
+            },
+            UnorderedList { tight, items } => UnorderedList {
+                tight: tight,
+                items: items.into_iter().map(|item| item.into_owned()).collect()
+            },
+            Paragraph(content) => Paragraph(content.into_owned()),
+            HorizontalRule => HorizontalRule
+        }
+    }
+}
+
+impl<'a> IntoOwned for Document<'a> {
+    type Owned = Document<'static>;
+
+    #[inline]
+    fn into_owned(self) -> Document<'static> {
+        self.into_iter().map(|b| b.into_owned()).collect()
+    }
+}
+
+impl<'a> IntoOwned for Text<'a> {
+    type Owned = Text<'static>;
+
+    #[inline]
+    fn into_owned(self) -> Text<'static> {
+        self.into_iter().map(|i| i.into_owned()).collect()
+    }
+}
+
+impl<'a> IntoOwned for Inline<'a> {
+    type Owned = Inline<'static>;
+
+    fn into_owned(self) -> Inline<'static> {
+        match self {
+            LineBreak => LineBreak,
+            Chunk(s) => Chunk(owned_str(s)),
+            Emphasis(content) => Emphasis(content.into_owned()),
+            MoreEmphasis(content) => MoreEmphasis(content.into_owned()),
+            Code(s) => Code(owned_str(s)),
+            HtmlInline(s) => HtmlInline(owned_str(s)),
+            Link { text, link, title, id } => Link {
+                text: text.map(|t| t.into_owned()),
+                link: owned_opt_str(link),
+                title: owned_opt_str(title),
+                id: owned_opt_str(id)
+            },
+            Image { alt, link, title, id } => Image {
+                alt: alt.into_owned(),
+                link: owned_opt_str(link),
+                title: owned_opt_str(title),
+                id: owned_opt_str(id)
+            }
+        }
     }
 }
 
@@ -82,61 +220,172 @@ pub trait FixLinks {
     fn fix_links(&mut self, link_map: &LinkMap);
 }
 
-impl FixLinks for Block {
+impl<'a> FixLinks for Block<'a> {
+    #[inline]
+    fn fix_links(&mut self, link_map: &LinkMap) {
+        LinkFixer { link_map: link_map }.visit_block(self)
+    }
+}
+
+impl<'a> FixLinks for Document<'a> {
+    #[inline]
     fn fix_links(&mut self, link_map: &LinkMap) {
-        match *self {
-            BlockQuote(ref mut content) => content.fix_links(link_map),
+        LinkFixer { link_map: link_map }.visit_document(self)
+    }
+}
 
-            OrderedList { ref mut items, .. } | UnorderedList { ref mut items } =>
-                for item in items.iter_mut() {
-                    item.fix_links(link_map);
-                },
+impl<'a> FixLinks for Text<'a> {
+    #[inline]
+    fn fix_links(&mut self, link_map: &LinkMap) {
+        LinkFixer { link_map: link_map }.visit_text(self)
+    }
+}
 
-            Paragraph(ref mut content) | Heading { ref mut content, .. } => 
-                content.fix_links(link_map),
+impl<'a> FixLinks for Inline<'a> {
+    #[inline]
+    fn fix_links(&mut self, link_map: &LinkMap) {
+        LinkFixer { link_map: link_map }.visit_inline(self)
+    }
+}
 
-            _ => {}
+/// Normalizes a reference label for matching: surrounding whitespace is
+/// stripped, inner whitespace runs are collapsed to a single space and the
+/// label is case-folded.
+pub fn normalize_label(label: &str) -> String {
+    let mut result = String::new();
+    for word in label.split(|c: char| c.is_whitespace()).filter(|w| !w.is_empty()) {
+        if !result.is_empty() {
+            result.push(' ');
         }
+        result.push_str(word.to_lowercase().as_slice());
     }
+    result
 }
 
-impl FixLinks for Document {
-    fn fix_links(&mut self, link_map: &LinkMap) {
-        for b in self.iter_mut() {
-            b.fix_links(link_map);
+/// Returns the textual content of inline elements, without any markup.
+pub fn text_content<'a>(text: &Text<'a>) -> String {
+    let mut result = String::new();
+    push_text_content(text, &mut result);
+    result
+}
+
+fn push_text_content<'a>(text: &Text<'a>, result: &mut String) {
+    for i in text.iter() {
+        match *i {
+            LineBreak => result.push(' '),
+            Chunk(ref s) | Code(ref s) => result.push_str(&**s),
+            Emphasis(ref content) | MoreEmphasis(ref content) =>
+                push_text_content(content, result),
+            Link { text: Some(ref content), .. } => push_text_content(content, result),
+            Link { text: None, link: Some(ref link), .. } => result.push_str(&**link),
+            Image { ref alt, .. } => push_text_content(alt, result),
+            _ => {}
         }
     }
 }
 
-impl FixLinks for Text {
-    fn fix_links(&mut self, link_map: &LinkMap) {
-        for i in self.iter_mut() {
-            i.fix_links(link_map);
+// Resolves reference links and images. References without a definition are
+// replaced with their source text.
+struct LinkFixer<'m> {
+    link_map: &'m LinkMap
+}
+
+impl<'m> LinkFixer<'m> {
+    fn resolve<'a>(&self, text: Option<&Text<'a>>, id: &str,
+                   link: &mut Option<Cow<'a, str>>, title: &mut Option<Cow<'a, str>>) {
+        // inline links have their destination already
+        if link.is_some() { return; }
+
+        // collapsed references, like `[label][]`, use their text as a label
+        let label = if id.trim().is_empty() {
+            text.map(text_content).unwrap_or(String::new())
+        } else {
+            id.to_string()
+        };
+
+        match self.link_map.get(&normalize_label(label.as_slice())) {
+            Some(ld) => {
+                *link = Some(Cow::Owned(ld.link.clone()));
+                if title.is_none() {
+                    *title = ld.title.clone().map(Cow::Owned);
+                }
+            }
+            None => {}
         }
     }
 }
 
-impl FixLinks for Inline {
-    fn fix_links(&mut self, link_map: &LinkMap) {
-        match *self {
-            Emphasis(ref mut content) | MoreEmphasis(ref mut content) =>
-                content.fix_links(link_map),
-
-            Link { ref mut link, ref mut title, id: Some(ref id), .. } => {
-                match link_map.get(id) {
-                    Some(ld) => {
-                        if link.is_none() {
-                            *link = Some(ld.link.clone());
-                        }
-                        if title.is_none() && ld.title.is_none() {
-                            *title = ld.title.clone();
-                        }
-                    }
-                    None => {}
+impl<'a, 'm> VisitorMut<'a> for LinkFixer<'m> {
+    fn visit_text(&mut self, text: &mut Text<'a>) {
+        let old = mem::replace(text, Vec::new());
+        for mut inline in old.into_iter() {
+            self.visit_inline(&mut inline);
+            if is_unresolved(&inline) {
+                for i in reference_source(inline).into_iter() {
+                    push_merging(text, i);
                 }
+            } else {
+                push_merging(text, inline);
             }
-            
+        }
+    }
+
+    fn visit_inline(&mut self, inline: &mut Inline<'a>) {
+        walk_inline_mut(self, inline);
+
+        match *inline {
+            Link { ref text, ref mut link, ref mut title, id: Some(ref id) } =>
+                self.resolve(text.as_ref(), &**id, link, title),
+
+            Image { ref alt, ref mut link, ref mut title, id: Some(ref id) } =>
+                self.resolve(Some(alt), &**id, link, title),
+
             _ => {}
         }
     }
 }
+
+fn is_unresolved(inline: &Inline) -> bool {
+    match *inline {
+        Link { link: None, id: Some(_), .. } | Image { link: None, id: Some(_), .. } => true,
+        _ => false
+    }
+}
+
+// reconstructs the source text of an unresolved reference
+fn reference_source<'a>(inline: Inline<'a>) -> Text<'a> {
+    let (prefix, text, id) = match inline {
+        Link { text, id, .. } => ("[", text.unwrap_or(Vec::new()), id),
+        Image { alt, id, .. } => ("![", alt, id),
+        other => return vec![other]
+    };
+
+    let label = text_content(&text);
+    let mut result = vec![Chunk(Cow::Borrowed(prefix))];
+    result.extend(text.into_iter());
+    result.push(Chunk(Cow::Borrowed("]")));
+
+    match id {
+        Some(id) => if id.is_empty() {
+            result.push(Chunk(Cow::Borrowed("[]")));
+        } else if &*id != label.as_slice() {
+            result.push(Chunk(Cow::Borrowed("[")));
+            result.push(Chunk(id));
+            result.push(Chunk(Cow::Borrowed("]")));
+        },
+        None => {}
+    }
+
+    result
+}
+
+// adds an inline element to the text, merging adjacent chunks
+fn push_merging<'a>(text: &mut Text<'a>, inline: Inline<'a>) {
+    if let Chunk(ref s1) = inline {
+        if let Some(&mut Chunk(ref mut s)) = text.last_mut() {
+            s.to_mut().push_str(&**s1);
+            return;
+        }
+    }
+    text.push(inline);
+}
@@ -1,4 +1,16 @@
 use std::cell::Cell;
+use std::borrow::Cow;
+
+macro_rules! impl_setters {
+    ($target:ident; $($name:ident : $t:ty),+) => ($(
+        impl $target {
+            pub fn $name(mut self, value: $t) -> $target {
+                self.$name = value;
+                self
+            }
+        }
+    )+)
+}
 
 pub trait CellOps<T> {
     fn modify<F: FnOnce(T) -> T>(&self, f: F);
@@ -14,6 +26,7 @@ pub trait CharOps {
     fn is_emphasis(self) -> bool;
     fn is_code(self) -> bool;
     fn is_space(self) -> bool;
+    fn is_line_end(self) -> bool;
     fn is_numeric(self) -> bool;
 }
 
@@ -27,7 +40,11 @@ impl CharOps for u8 {
     }
 
     fn is_space(self) -> bool {
-        self == b' ' || self == b'\n'
+        self == b' ' || self.is_line_end()
+    }
+
+    fn is_line_end(self) -> bool {
+        self == b'\n' || self == b'\r'
     }
 
     fn is_numeric(self) -> bool {
@@ -36,6 +53,32 @@ impl CharOps for u8 {
     }
 }
 
+/// Replaces CRLF and CR line endings with LF, borrowing if there are none.
+pub fn normalize_line_endings<'a>(s: &'a str) -> Cow<'a, str> {
+    if !s.contains('\r') { return Cow::Borrowed(s) }
+
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\r' {
+            if chars.peek() == Some(&'\n') { chars.next(); }
+            result.push('\n');
+        } else {
+            result.push(c);
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// Decodes bytes of the source, replacing invalid UTF-8 sequences, and
+/// normalizes line endings in them.
+pub fn decode_text<'a>(s: &'a [u8]) -> Cow<'a, str> {
+    match String::from_utf8_lossy(s) {
+        Cow::Borrowed(s) => normalize_line_endings(s),
+        Cow::Owned(s) => Cow::Owned(normalize_line_endings(s.as_slice()).into_owned())
+    }
+}
+
 pub trait ByteMatcher {
     fn matches(&mut self, b: u8) -> bool;
 }
@@ -0,0 +1,197 @@
+//! Generic traversals over the token tree.
+//!
+//! `Visitor` and `VisitorMut` walk the tree by reference; every method has a
+//! default implementation which descends into children via the corresponding
+//! `walk_*` function, so an implementation only needs to override methods for
+//! the nodes it is interested in. Call the `walk_*` function from an
+//! overridden method to continue the descent.
+//!
+//! `Fold` consumes the tree and rebuilds it, allowing nodes to be replaced or
+//! removed.
+
+use tokens::*;
+
+pub trait Visitor<'a> {
+    #[inline]
+    fn visit_document(&mut self, doc: &Document<'a>) { walk_document(self, doc) }
+
+    #[inline]
+    fn visit_block(&mut self, block: &Block<'a>) { walk_block(self, block) }
+
+    #[inline]
+    fn visit_list_item(&mut self, item: &Document<'a>) { walk_document(self, item) }
+
+    #[inline]
+    fn visit_text(&mut self, text: &Text<'a>) { walk_text(self, text) }
+
+    #[inline]
+    fn visit_inline(&mut self, inline: &Inline<'a>) { walk_inline(self, inline) }
+}
+
+pub fn walk_document<'a, V: ?Sized + Visitor<'a>>(v: &mut V, doc: &Document<'a>) {
+    for block in doc.iter() {
+        v.visit_block(block);
+    }
+}
+
+pub fn walk_block<'a, V: ?Sized + Visitor<'a>>(v: &mut V, block: &Block<'a>) {
+    match *block {
+        Heading { ref content, .. } => v.visit_text(content),
+        BlockQuote(ref content) => v.visit_document(content),
+        BlockCode { .. } | HtmlBlock(_) => {}
+        OrderedList { ref items, .. } | UnorderedList { ref items, .. } =>
+            for item in items.iter() {
+                v.visit_list_item(item);
+            },
+        Paragraph(ref content) => v.visit_text(content),
+        HorizontalRule => {}
+    }
+}
+
+pub fn walk_text<'a, V: ?Sized + Visitor<'a>>(v: &mut V, text: &Text<'a>) {
+    for inline in text.iter() {
+        v.visit_inline(inline);
+    }
+}
+
+pub fn walk_inline<'a, V: ?Sized + Visitor<'a>>(v: &mut V, inline: &Inline<'a>) {
+    match *inline {
+        LineBreak | Chunk(_) | Code(_) | HtmlInline(_) => {}
+        Emphasis(ref content) | MoreEmphasis(ref content) => v.visit_text(content),
+        Link { ref text, .. } => match *text {
+            Some(ref text) => v.visit_text(text),
+            None => {}
+        },
+        Image { ref alt, .. } => v.visit_text(alt)
+    }
+}
+
+pub trait VisitorMut<'a> {
+    #[inline]
+    fn visit_document(&mut self, doc: &mut Document<'a>) { walk_document_mut(self, doc) }
+
+    #[inline]
+    fn visit_block(&mut self, block: &mut Block<'a>) { walk_block_mut(self, block) }
+
+    #[inline]
+    fn visit_list_item(&mut self, item: &mut Document<'a>) { walk_document_mut(self, item) }
+
+    #[inline]
+    fn visit_text(&mut self, text: &mut Text<'a>) { walk_text_mut(self, text) }
+
+    #[inline]
+    fn visit_inline(&mut self, inline: &mut Inline<'a>) { walk_inline_mut(self, inline) }
+}
+
+pub fn walk_document_mut<'a, V: ?Sized + VisitorMut<'a>>(v: &mut V, doc: &mut Document<'a>) {
+    for block in doc.iter_mut() {
+        v.visit_block(block);
+    }
+}
+
+pub fn walk_block_mut<'a, V: ?Sized + VisitorMut<'a>>(v: &mut V, block: &mut Block<'a>) {
+    match *block {
+        Heading { ref mut content, .. } => v.visit_text(content),
+        BlockQuote(ref mut content) => v.visit_document(content),
+        BlockCode { .. } | HtmlBlock(_) => {}
+        OrderedList { ref mut items, .. } | UnorderedList { ref mut items, .. } =>
+            for item in items.iter_mut() {
+                v.visit_list_item(item);
+            },
+        Paragraph(ref mut content) => v.visit_text(content),
+        HorizontalRule => {}
+    }
+}
+
+pub fn walk_text_mut<'a, V: ?Sized + VisitorMut<'a>>(v: &mut V, text: &mut Text<'a>) {
+    for inline in text.iter_mut() {
+        v.visit_inline(inline);
+    }
+}
+
+pub fn walk_inline_mut<'a, V: ?Sized + VisitorMut<'a>>(v: &mut V, inline: &mut Inline<'a>) {
+    match *inline {
+        LineBreak | Chunk(_) | Code(_) | HtmlInline(_) => {}
+        Emphasis(ref mut content) | MoreEmphasis(ref mut content) => v.visit_text(content),
+        Link { ref mut text, .. } => match *text {
+            Some(ref mut text) => v.visit_text(text),
+            None => {}
+        },
+        Image { ref mut alt, .. } => v.visit_text(alt)
+    }
+}
+
+/// A consuming transformation of the token tree.
+///
+/// `fold_block` and `fold_inline` return `None` to remove the node from its
+/// parent. To replace a node with several nodes, override `fold_document` or
+/// `fold_text` instead.
+pub trait Fold<'a> {
+    #[inline]
+    fn fold_document(&mut self, doc: Document<'a>) -> Document<'a> { fold_document(self, doc) }
+
+    #[inline]
+    fn fold_block(&mut self, block: Block<'a>) -> Option<Block<'a>> {
+        Some(fold_block(self, block))
+    }
+
+    #[inline]
+    fn fold_list_item(&mut self, item: Document<'a>) -> Document<'a> { fold_document(self, item) }
+
+    #[inline]
+    fn fold_text(&mut self, text: Text<'a>) -> Text<'a> { fold_text(self, text) }
+
+    #[inline]
+    fn fold_inline(&mut self, inline: Inline<'a>) -> Option<Inline<'a>> {
+        Some(fold_inline(self, inline))
+    }
+}
+
+pub fn fold_document<'a, F: ?Sized + Fold<'a>>(f: &mut F, doc: Document<'a>) -> Document<'a> {
+    doc.into_iter().filter_map(|b| f.fold_block(b)).collect()
+}
+
+/// Folds children of the block, keeping the block itself.
+pub fn fold_block<'a, F: ?Sized + Fold<'a>>(f: &mut F, block: Block<'a>) -> Block<'a> {
+    match block {
+        Heading { level, content } => Heading { level: level, content: f.fold_text(content) },
+        BlockQuote(content) => BlockQuote(f.fold_document(content)),
+        b @ BlockCode { .. } | b @ HtmlBlock(_) => b,
+        OrderedList { start_index, tight, items } => OrderedList {
+            start_index: start_index,
+            tight: tight,
+            items: items.into_iter().map(|item| f.fold_list_item(item)).collect()
+        },
+        UnorderedList { tight, items } => UnorderedList {
+            tight: tight,
+            items: items.into_iter().map(|item| f.fold_list_item(item)).collect()
+        },
+        Paragraph(content) => Paragraph(f.fold_text(content)),
+        HorizontalRule => HorizontalRule
+    }
+}
+
+pub fn fold_text<'a, F: ?Sized + Fold<'a>>(f: &mut F, text: Text<'a>) -> Text<'a> {
+    text.into_iter().filter_map(|i| f.fold_inline(i)).collect()
+}
+
+/// Folds children of the inline element, keeping the element itself.
+pub fn fold_inline<'a, F: ?Sized + Fold<'a>>(f: &mut F, inline: Inline<'a>) -> Inline<'a> {
+    match inline {
+        i @ LineBreak | i @ Chunk(_) | i @ Code(_) | i @ HtmlInline(_) => i,
+        Emphasis(content) => Emphasis(f.fold_text(content)),
+        MoreEmphasis(content) => MoreEmphasis(f.fold_text(content)),
+        Link { text, link, title, id } => Link {
+            text: text.map(|t| f.fold_text(t)),
+            link: link,
+            title: title,
+            id: id
+        },
+        Image { alt, link, title, id } => Image {
+            alt: f.fold_text(alt),
+            link: link,
+            title: title,
+            id: id
+        }
+    }
+}
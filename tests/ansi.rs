@@ -0,0 +1,53 @@
+extern crate md;
+
+use std::borrow::Cow;
+
+use md::MarkdownParser;
+use md::tokens::*;
+use md::render::ansi::{self, AnsiStyle};
+
+fn link<'a>(text: &'a str, dest: &'a str) -> Inline<'a> {
+    Link {
+        text: Some(vec![Chunk(Cow::Borrowed(text))]),
+        link: Some(Cow::Borrowed(dest)),
+        title: None,
+        id: None
+    }
+}
+
+#[test]
+fn no_escapes_without_colors() {
+    let doc = MarkdownParser::new(b"# Title\n\n*a* **b** `c`\n").read_document();
+    let rendered = ansi::to_ansi_with_style(&doc.blocks, AnsiStyle::default().colors(false));
+    assert_eq!(rendered, "# Title\n\na b c\n");
+}
+
+#[test]
+fn control_characters_are_removed() {
+    let doc = vec![
+        Paragraph(vec![Chunk(Cow::Borrowed("a\x1b[31mb\x07c"))]),
+        BlockCode { tag: None, content: Cow::Borrowed("x\x1b]8;;y\n") }
+    ];
+    let rendered = ansi::to_ansi_with_style(&doc, AnsiStyle::default().colors(false));
+    assert_eq!(rendered, "a[31mbc\n\n    x]8;;y\n");
+}
+
+#[test]
+fn links_are_hyperlinks() {
+    let doc = vec![Paragraph(vec![link("x", "http://a")])];
+    assert_eq!(ansi::to_ansi(&doc), "\x1b[4m\x1b]8;;http://a\x1b\\x\x1b]8;;\x1b\\\x1b[0m\n");
+    assert_eq!(ansi::to_ansi_with_style(&doc, AnsiStyle::default().hyperlinks(false)), "x <http://a>\n");
+}
+
+#[test]
+fn unsafe_link_is_not_a_hyperlink() {
+    let doc = vec![Paragraph(vec![link("x", "http://a\x1b]8;;evil")])];
+    assert_eq!(ansi::to_ansi(&doc), "x <http://a]8;;evil>\n");
+}
+
+#[test]
+fn paragraphs_are_wrapped() {
+    let doc = MarkdownParser::new(b"one two three four\n").read_document();
+    let rendered = ansi::to_ansi_with_style(&doc.blocks, AnsiStyle::default().width(9));
+    assert_eq!(rendered, "one two\nthree\nfour\n");
+}
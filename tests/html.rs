@@ -0,0 +1,19 @@
+extern crate md;
+
+use std::borrow::Cow;
+
+use md::html::from_html;
+use md::tokens::*;
+
+#[test]
+fn raw_text_element_with_non_ascii_body() {
+    // lowercasing changes the byte length of these characters
+    for body in ["\u{212a}", "\u{130}\u{130}\u{130}\u{130}"].iter() {
+        let source = format!("<style>{}</style><p>after</p>", body);
+        let doc = from_html(source.as_slice());
+        assert_eq!(doc, vec![
+            HtmlBlock(Cow::Owned(format!("<style>{}</style>", body))),
+            Paragraph(vec![Chunk(Cow::Owned("after".to_string()))])
+        ]);
+    }
+}
@@ -0,0 +1,45 @@
+extern crate md;
+
+use std::borrow::Cow;
+
+use md::MarkdownParser;
+use md::tokens::*;
+use md::render::latex::{self, LatexStyle};
+
+fn render(source: &str) -> String {
+    let doc = MarkdownParser::new(source.as_bytes()).read_document();
+    latex::to_latex(&doc.blocks)
+}
+
+#[test]
+fn special_characters_are_escaped() {
+    assert_eq!(render("50% of $x_1$ & {y}\n\n[a](http://x/#b%20)\n"),
+               "50\\% of \\$x\\_1\\$ \\& \\{y\\}\n\n\\href{http://x/\\#b\\%20}{a}\n");
+}
+
+#[test]
+fn items_start_with_empty_braces() {
+    let doc = vec![UnorderedList {
+        tight: true,
+        items: vec![vec![Paragraph(vec![Chunk(Cow::Borrowed("[x] a"))])], vec![]]
+    }];
+    assert_eq!(latex::to_latex(&doc), "\\begin{itemize}\n\\item{} [x] a\n\\item{} \n\\end{itemize}\n");
+    assert_eq!(render("3. a\n"), "\\begin{enumerate}\n\\setcounter{enumi}{2}\n\\item{} a\n\\end{enumerate}\n");
+}
+
+#[test]
+fn code_blocks() {
+    assert_eq!(render("```\na\n```\n"), "\\begin{verbatim}\na\n\\end{verbatim}\n");
+
+    let doc = MarkdownParser::new(b"```c++\na\n```\n").read_document();
+    assert_eq!(latex::to_latex_with_style(&doc.blocks, LatexStyle::default().listings(true)),
+               "\\begin{lstlisting}[language=c++]\na\n\\end{lstlisting}\n");
+}
+
+#[test]
+fn code_containing_the_environment_end() {
+    let doc = vec![BlockCode { tag: None, content: Cow::Borrowed("a\n\\end{verbatim}\n\nb|!\n") }];
+    assert_eq!(latex::to_latex(&doc),
+               "\\begin{flushleft}\n\\verb|a|\\\\\n\\verb|\\end{verbatim}|\\\\\n\\mbox{}\\\\\n\\verb+b|!+\n\
+                \\end{flushleft}\n");
+}
@@ -0,0 +1,62 @@
+extern crate md;
+
+use md::MarkdownParser;
+use md::render::markdown::{self, MarkdownStyle, HeadingStyle};
+
+// renders the source and checks that the result is parsed to the same blocks
+fn round_trip(source: &str, style: MarkdownStyle) -> String {
+    let doc = MarkdownParser::new(source.as_bytes()).read_document();
+    let rendered = markdown::to_markdown_with_style(&doc.blocks, &doc.link_map, style);
+    let reparsed = MarkdownParser::new(rendered.as_bytes()).read_document();
+    assert_eq!(reparsed.blocks, doc.blocks);
+    rendered
+}
+
+#[test]
+fn nested_tight_lists() {
+    for source in ["- a\n  - b\n  - c\n- d\n", "1. a\n   1. b\n2. c\n"].iter() {
+        assert_eq!(round_trip(source, MarkdownStyle::default()), source.to_string());
+    }
+}
+
+#[test]
+fn nested_loose_list() {
+    let source = "- a\n\n  - b\n\n- c\n";
+    assert_eq!(round_trip(source, MarkdownStyle::default()), source.to_string());
+}
+
+#[test]
+fn tight_item_with_rule_and_heading() {
+    let style = MarkdownStyle::default().heading_style(HeadingStyle::Setext);
+    assert_eq!(round_trip("- a\n  ***\n- b\n  # c\n", style), "- a\n  ***\n- b\n  # c\n");
+}
+
+#[test]
+fn escapes_only_block_markers() {
+    for source in ["\\# not a heading\n", "\\- not an item\n", "1\\. not an item\n",
+                   "\\> not a quote\n", "text\n\\=\n"].iter() {
+        round_trip(source, MarkdownStyle::default());
+    }
+    assert_eq!(round_trip("a # b - c\n", MarkdownStyle::default()), "a # b - c\n");
+}
+
+#[test]
+fn code_fence_longer_than_content() {
+    let rendered = round_trip("````rust\nlet a = \"```\";\n````\n", MarkdownStyle::default());
+    assert_eq!(rendered, "````rust\nlet a = \"```\";\n````\n");
+}
+
+#[test]
+fn reference_definitions_are_kept() {
+    let rendered = round_trip("[a][r] and [b]\n\n[r]: /url\n[b]: /b \"title\"\n", MarkdownStyle::default());
+    assert_eq!(rendered, "[a][r] and [b]\n\n[b]: /b \"title\"\n[r]: /url\n");
+}
+
+#[test]
+fn adjacent_lists_of_the_same_kind() {
+    let doc = MarkdownParser::new(b"- a\n- b\n\n* c\n\n1. d\n\n1) e\n").read_document();
+    assert_eq!(doc.blocks.len(), 4);
+    let rendered = markdown::to_markdown(&doc.blocks, &doc.link_map);
+    assert_eq!(rendered, "- a\n- b\n\n* c\n\n1. d\n\n1) e\n");
+    assert_eq!(MarkdownParser::new(rendered.as_bytes()).read_all(), doc.blocks);
+}
@@ -0,0 +1,48 @@
+extern crate md;
+
+use std::borrow::Cow;
+
+use md::MarkdownParser;
+use md::tokens::*;
+use md::render::plain::{self, PlainStyle};
+
+fn render(source: &str, style: PlainStyle) -> String {
+    let doc = MarkdownParser::new(source.as_bytes()).read_document();
+    plain::to_plain_text_with_style(&doc.blocks, style)
+}
+
+#[test]
+fn link_and_image_text_is_kept() {
+    assert_eq!(render("See [the docs](http://x) and ![a logo](l.png), `code`.\n", PlainStyle::default()),
+               "See the docs and a logo, code.");
+}
+
+#[test]
+fn lists_and_quotes() {
+    let source = "# Title\n\n- one\n- two\n\n> quoted\n\n3. three\n4. four\n";
+    assert_eq!(render(source, PlainStyle::default()), "Title\n\n- one\n- two\n\n> quoted\n\n3. three\n4. four");
+}
+
+#[test]
+fn code_blocks_can_be_skipped() {
+    let source = "text\n\n```\ncode\n```\n";
+    assert_eq!(render(source, PlainStyle::default()), "text\n\ncode");
+    assert_eq!(render(source, PlainStyle::default().code_blocks(false)), "text");
+}
+
+#[test]
+fn soft_breaks_become_spaces_and_hard_breaks_newlines() {
+    let doc = vec![Paragraph(vec![
+        Chunk(Cow::Borrowed("a\nb")),
+        LineBreak,
+        Emphasis(vec![Chunk(Cow::Borrowed("c"))])
+    ])];
+    assert_eq!(plain::to_plain_text(&doc), "a b\nc");
+}
+
+#[test]
+fn limit_cuts_at_a_word_boundary() {
+    let source = "one two three four\n";
+    assert_eq!(render(source, PlainStyle::default().limit(10)), "one two…");
+    assert_eq!(render(source, PlainStyle::default().limit(18)), "one two three four");
+}
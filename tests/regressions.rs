@@ -0,0 +1,81 @@
+extern crate md;
+
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use md::{MarkdownParser, StreamingParser};
+use md::render::{markdown, ansi, latex, plain, roff, xml};
+use md::tokens::*;
+
+// every input which once made the fuzzer fail
+fn regressions() -> Vec<Vec<u8>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("regressions");
+    let mut paths: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    paths.sort();
+    paths.iter().map(|path| {
+        let mut buf = Vec::new();
+        File::open(path).and_then(|mut f| f.read_to_end(&mut buf)).unwrap();
+        buf
+    }).collect()
+}
+
+#[test]
+fn regressions_parse() {
+    for input in regressions().iter() {
+        MarkdownParser::new(input.as_slice()).read_all();
+    }
+}
+
+#[test]
+fn regressions_stream() {
+    for input in regressions().iter() {
+        for _ in StreamingParser::new(io::Cursor::new(input.clone())) {}
+    }
+}
+
+#[test]
+fn regressions_render() {
+    for input in regressions().iter() {
+        let doc = MarkdownParser::new(input.as_slice()).read_document();
+        markdown::to_markdown(&doc.blocks, &doc.link_map);
+        ansi::to_ansi(&doc.blocks);
+        latex::to_latex(&doc.blocks);
+        plain::to_plain_text(&doc.blocks);
+        roff::to_roff(&doc.blocks);
+        xml::to_xml(&doc.blocks);
+
+        let (doc, spans) = MarkdownParser::new(input.as_slice()).read_all_with_spans();
+        xml::to_xml_with_sourcepos(&doc, spans.as_slice());
+    }
+}
+
+fn paragraph<'a, 'b>(doc: &'b Document<'a>) -> &'b Text<'a> {
+    assert_eq!(doc.len(), 1);
+    match doc[0] {
+        Paragraph(ref content) => content,
+        ref block => panic!("{:?} is not a paragraph", block)
+    }
+}
+
+fn has_image(text: &Text) -> bool {
+    text.iter().any(|i| match *i {
+        Image { .. } => true,
+        _ => false
+    })
+}
+
+#[test]
+fn escaped_image_marker() {
+    for &(source, chunk) in [("\\![a]", "!"), ("a\\![b](c)", "a!")].iter() {
+        let doc = MarkdownParser::new(source.as_bytes()).read_all();
+        let content = paragraph(&doc);
+        assert!(!has_image(content), "{} has an image", source);
+        assert_eq!(content[0], Chunk(Cow::Borrowed(chunk)));
+    }
+
+    // an escaped backslash leaves the image marker alone
+    let doc = MarkdownParser::new(b"\\\\![d](e)").read_all();
+    assert!(has_image(paragraph(&doc)));
+}
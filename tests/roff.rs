@@ -0,0 +1,33 @@
+extern crate md;
+
+use md::MarkdownParser;
+use md::render::roff::{self, RoffStyle};
+
+fn render(source: &str) -> String {
+    let doc = MarkdownParser::new(source.as_bytes()).read_document();
+    roff::to_roff(&doc.blocks)
+}
+
+#[test]
+fn headings_and_fonts() {
+    let doc = MarkdownParser::new(b"# Name\n\nSome *text* and **bold**.\n\n## Usage\n\n### Deep\n").read_document();
+    let rendered = roff::to_roff_with_style(&doc.blocks, RoffStyle::default().title(Some("md".to_string())));
+    assert_eq!(rendered, ".TH \"md\" \"1\"\n.SH\nName\n.PP\nSome \\fItext\\fR and \\fBbold\\fR.\n\
+                          .SS\nUsage\n.PP\n\\fBDeep\\fR\n");
+}
+
+#[test]
+fn code_blocks_are_not_filled() {
+    assert_eq!(render("```\n.x\n-a\\b\n```\n"), ".PP\n.RS 4\n.nf\n\\&.x\n\\-a\\eb\n.fi\n.RE\n");
+}
+
+#[test]
+fn lists() {
+    assert_eq!(render("- a\n- b\n\n1. c\n   - d\n"),
+               ".IP \\(bu 2\na\n.IP \\(bu 2\nb\n.TP 4\n1.\nc\n.RS\n.IP \\(bu 2\nd\n.RE\n");
+}
+
+#[test]
+fn quotes_and_links() {
+    assert_eq!(render("> see [this](http://x)\n"), ".RS 4\n.PP\nsee this <http://x>\n.RE\n");
+}
@@ -0,0 +1,73 @@
+extern crate md;
+
+use std::borrow::Cow;
+
+use md::MarkdownParser;
+use md::tokens::*;
+use md::parser::{Span, Position};
+use md::render::xml;
+
+const HEADER: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                              <!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n\
+                              <document xmlns=\"http://commonmark.org/xml/1.0\">\n";
+
+fn span(line: usize, start: usize, end: usize) -> Span {
+    Span {
+        start: Position { offset: start - 1, line: line, column: start },
+        end: Position { offset: end - 1, line: line, column: end }
+    }
+}
+
+#[test]
+fn sourcepos_of_blocks_and_items() {
+    let doc = vec![
+        UnorderedList { tight: true, items: vec![vec![Paragraph(vec![Chunk(Cow::Borrowed("a"))])]] },
+        Paragraph(vec![Chunk(Cow::Borrowed("b\nc"))])
+    ];
+    // the last paragraph has no span
+    let spans = [span(1, 1, 4), span(1, 1, 4), span(1, 3, 4)];
+    let expected = HEADER.to_string() +
+        "  <list type=\"bullet\" tight=\"true\" sourcepos=\"1:1-1:3\">\n\
+        \x20   <item sourcepos=\"1:1-1:3\">\n\
+        \x20     <paragraph sourcepos=\"1:3-1:3\">\n\
+        \x20       <text xml:space=\"preserve\">a</text>\n\
+        \x20     </paragraph>\n\
+        \x20   </item>\n\
+        \x20 </list>\n\
+        \x20 <paragraph>\n\
+        \x20   <text xml:space=\"preserve\">b</text>\n\
+        \x20   <softbreak />\n\
+        \x20   <text xml:space=\"preserve\">c</text>\n\
+        \x20 </paragraph>\n\
+        </document>\n";
+    assert_eq!(xml::to_xml_with_sourcepos(&doc, &spans), expected);
+}
+
+#[test]
+fn parsed_spans_start_at_their_blocks() {
+    let (doc, spans) = MarkdownParser::new(b"# a\n\n- b\n").read_all_with_spans();
+    let rendered = xml::to_xml_with_sourcepos(&doc, spans.as_slice());
+    assert!(rendered.contains("<heading level=\"1\" sourcepos=\"1:1-"));
+    assert!(rendered.contains("<list type=\"bullet\" tight=\"true\" sourcepos=\"3:1-"));
+    assert!(rendered.contains("<item sourcepos=\"3:1-"));
+}
+
+#[test]
+fn tightness_of_lists() {
+    let loose = xml::to_xml(&MarkdownParser::new(b"- a\n\n- b\n").read_all());
+    assert!(loose.contains("<list type=\"bullet\" tight=\"false\">"));
+    let tight = xml::to_xml(&MarkdownParser::new(b"3. a\n4. b\n").read_all());
+    assert!(tight.contains("<list type=\"ordered\" start=\"3\" delim=\"period\" tight=\"true\">"));
+}
+
+#[test]
+fn text_is_escaped() {
+    let doc = vec![Paragraph(vec![Link {
+        text: Some(vec![Chunk(Cow::Borrowed("a < b"))]),
+        link: Some(Cow::Borrowed("/x?a=1&b=\"2\"")),
+        title: None,
+        id: None
+    }])];
+    assert!(xml::to_xml(&doc).contains("    <link destination=\"/x?a=1&amp;b=&quot;2&quot;\" title=\"\">\n\
+                                        \x20     <text xml:space=\"preserve\">a &lt; b</text>\n"));
+}